@@ -0,0 +1,276 @@
+// Exports of the resolved configuration for non-Rust consumers
+//
+// config.rs covers Rust code; the files generated here expose the same
+// values to C code compiled by build scripts (autoconf.h), make (auto.conf),
+// shell scripts (config.sh) and any other tooling (config.json).
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub use kbuild_build::dotconfig::unquote_string;
+
+/// An output file selectable via `[workspace.metadata.kbuild] outputs`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Output {
+    AutoconfH,
+    Json,
+    AutoConf,
+    Shell,
+}
+
+impl Output {
+    /// Outputs generated when `outputs` is not set
+    pub const DEFAULT: &'static [Output] = &[Output::AutoconfH];
+
+    /// Parse an output name as written in `outputs = [...]`
+    pub fn from_name(name: &str) -> Result<Output, String> {
+        match name {
+            "autoconf.h" => Ok(Output::AutoconfH),
+            "config.json" => Ok(Output::Json),
+            "auto.conf" => Ok(Output::AutoConf),
+            "config.sh" => Ok(Output::Shell),
+            _ => Err(format!(
+                "Unknown kbuild output '{}' (expected one of: autoconf.h, config.json, auto.conf, config.sh)",
+                name
+            )),
+        }
+    }
+
+    /// File name of the output inside target/kbuild
+    pub fn file_name(self) -> &'static str {
+        match self {
+            Output::AutoconfH => "autoconf.h",
+            Output::Json => "config.json",
+            Output::AutoConf => "auto.conf",
+            Output::Shell => "config.sh",
+        }
+    }
+}
+
+/// Typed view of a raw .config value
+enum Value {
+    Bool(bool),
+    Module,
+    Int(i64),
+    Hex(String),
+    Str(String),
+}
+
+impl Value {
+    fn parse(raw: &str) -> Value {
+        match raw {
+            "y" => Value::Bool(true),
+            "n" => Value::Bool(false),
+            "m" => Value::Module,
+            _ => {
+                if let Some(hex) = raw.strip_prefix("0x").or_else(|| raw.strip_prefix("0X")) {
+                    if !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                        return Value::Hex(raw.to_string());
+                    }
+                }
+                if let Ok(int) = raw.parse::<i64>() {
+                    return Value::Int(int);
+                }
+                Value::Str(unquote_string(raw).unwrap_or_else(|| raw.to_string()))
+            }
+        }
+    }
+}
+
+/// Map a .config key to its C macro name (`SMP` -> `CONFIG_SMP`)
+fn c_symbol(key: &str) -> String {
+    if key.starts_with("CONFIG_") {
+        key.to_string()
+    } else {
+        format!("CONFIG_{}", key)
+    }
+}
+
+/// Escape a string for use inside a C string literal
+fn escape_c_string(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => result.push_str("\\\\"),
+            '"' => result.push_str("\\\""),
+            '\n' => result.push_str("\\n"),
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+/// Quote a string for POSIX shells
+fn quote_shell(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// (key, typed value) pairs in symbol order
+fn typed_entries(config: &BTreeMap<String, String>) -> impl Iterator<Item = (&String, Value)> {
+    config.iter().map(|(k, v)| (k, Value::parse(v)))
+}
+
+/// Generate target/kbuild/autoconf.h, matching Linux's include/generated/autoconf.h
+///
+/// * `y` -> `#define CONFIG_X 1`
+/// * `m` -> `#define CONFIG_X_MODULE 1`
+/// * `n` -> omitted
+/// * ints/hex -> emitted verbatim, strings -> escaped C string literals
+fn render_autoconf_h(config: &BTreeMap<String, String>) -> String {
+    let mut content = String::new();
+    content.push_str("/*\n");
+    content.push_str(" * Automatically generated file; DO NOT EDIT.\n");
+    content.push_str(" * Generated by cargo-kbuild from .config\n");
+    content.push_str(" */\n");
+
+    for (key, value) in typed_entries(config) {
+        let symbol = c_symbol(key);
+        match value {
+            Value::Bool(true) => content.push_str(&format!("#define {} 1\n", symbol)),
+            Value::Bool(false) => {}
+            Value::Module => content.push_str(&format!("#define {}_MODULE 1\n", symbol)),
+            Value::Int(int) => content.push_str(&format!("#define {} {}\n", symbol, int)),
+            Value::Hex(hex) => content.push_str(&format!("#define {} {}\n", symbol, hex)),
+            Value::Str(string) => {
+                content.push_str(&format!("#define {} \"{}\"\n", symbol, escape_c_string(&string)));
+            }
+        }
+    }
+
+    content
+}
+
+/// Generate target/kbuild/auto.conf, matching Linux's include/config/auto.conf
+///
+/// The file can be `include`d from a Makefile. Symbols set to `n` are omitted.
+fn render_auto_conf(config: &BTreeMap<String, String>) -> String {
+    let mut content = String::new();
+    content.push_str("#\n");
+    content.push_str("# Automatically generated file; DO NOT EDIT.\n");
+    content.push_str("# Generated by cargo-kbuild from .config\n");
+    content.push_str("#\n");
+
+    for (key, value) in typed_entries(config) {
+        let symbol = c_symbol(key);
+        match value {
+            Value::Bool(true) => content.push_str(&format!("{}=y\n", symbol)),
+            Value::Bool(false) => {}
+            Value::Module => content.push_str(&format!("{}=m\n", symbol)),
+            Value::Int(int) => content.push_str(&format!("{}={}\n", symbol, int)),
+            Value::Hex(hex) => content.push_str(&format!("{}={}\n", symbol, hex)),
+            Value::Str(string) => {
+                content.push_str(&format!("{}=\"{}\"\n", symbol, escape_c_string(&string)));
+            }
+        }
+    }
+
+    content
+}
+
+/// Generate target/kbuild/config.sh, which can be sourced by POSIX shells
+///
+/// Symbols set to `n` are omitted, so `[ -n "$CONFIG_X" ]` tests for enabled.
+fn render_shell(config: &BTreeMap<String, String>) -> String {
+    let mut content = String::new();
+    content.push_str("# Automatically generated file; DO NOT EDIT.\n");
+    content.push_str("# Generated by cargo-kbuild from .config\n");
+
+    for (key, value) in typed_entries(config) {
+        let symbol = c_symbol(key);
+        match value {
+            Value::Bool(true) => content.push_str(&format!("{}=y\n", symbol)),
+            Value::Bool(false) => {}
+            Value::Module => content.push_str(&format!("{}=m\n", symbol)),
+            Value::Int(int) => content.push_str(&format!("{}={}\n", symbol, int)),
+            Value::Hex(hex) => content.push_str(&format!("{}={}\n", symbol, hex)),
+            Value::Str(string) => content.push_str(&format!("{}={}\n", symbol, quote_shell(&string))),
+        }
+    }
+
+    content
+}
+
+/// Generate target/kbuild/config.json with typed values and their sources
+///
+/// ```json
+/// { "config_hash": "...", "symbols": { "SMP": { "type": "bool", "value": true, "source": ".config:2" } } }
+/// ```
+fn render_json(
+    config: &BTreeMap<String, String>,
+    sources: &BTreeMap<String, String>,
+    config_hash: &str,
+) -> Result<String, String> {
+    let mut symbols = serde_json::Map::new();
+
+    for (key, value) in typed_entries(config) {
+        let (kind, value) = match value {
+            Value::Bool(b) => ("bool", serde_json::Value::from(b)),
+            Value::Module => ("tristate", serde_json::Value::from("m")),
+            Value::Int(int) => ("int", serde_json::Value::from(int)),
+            Value::Hex(hex) => ("hex", serde_json::Value::from(hex)),
+            Value::Str(string) => ("string", serde_json::Value::from(string)),
+        };
+
+        let mut symbol = serde_json::Map::new();
+        symbol.insert("type".to_string(), kind.into());
+        symbol.insert("value".to_string(), value);
+        if let Some(source) = sources.get(key) {
+            symbol.insert("source".to_string(), source.as_str().into());
+        }
+        symbols.insert(key.clone(), symbol.into());
+    }
+
+    let mut root = serde_json::Map::new();
+    root.insert("config_hash".to_string(), config_hash.into());
+    root.insert("symbols".to_string(), symbols.into());
+
+    let mut content = serde_json::to_string_pretty(&serde_json::Value::Object(root))
+        .map_err(|e| format!("Failed to serialize config.json: {}", e))?;
+    content.push('\n');
+    Ok(content)
+}
+
+/// Generate the selected exports into target/kbuild
+///
+/// * autoconf.h - `#define CONFIG_X 1`, `CONFIG_X_MODULE` for `m`, like Linux
+/// * auto.conf - make-compatible `CONFIG_X=y` assignments
+/// * config.sh - shell-quoted assignments for `. target/kbuild/config.sh`
+/// * config.json - typed values with the file and line each symbol came from
+///
+/// Returns the path of autoconf.h if it was generated.
+pub fn generate_exports(
+    workspace_root: &Path,
+    outputs: &[Output],
+    config: &BTreeMap<String, String>,
+    sources: &BTreeMap<String, String>,
+    config_hash: &str,
+) -> Result<Option<PathBuf>, String> {
+    let target_dir = workspace_root.join("target/kbuild");
+    fs::create_dir_all(&target_dir)
+        .map_err(|e| format!("Failed to create target/kbuild: {}", e))?;
+
+    let mut autoconf_path = None;
+
+    for &output in outputs {
+        let content = match output {
+            Output::AutoconfH => render_autoconf_h(config),
+            Output::Json => render_json(config, sources, config_hash)?,
+            Output::AutoConf => render_auto_conf(config),
+            Output::Shell => render_shell(config),
+        };
+
+        let path = target_dir.join(output.file_name());
+        if super::write_if_changed(&path, &content)? {
+            println!("📝 Generated {} at: {}", output.file_name(), path.display());
+        } else {
+            println!("✔️  {} is up to date", output.file_name());
+        }
+
+        if output == Output::AutoconfH {
+            autoconf_path = Some(path);
+        }
+    }
+
+    Ok(autoconf_path)
+}
@@ -0,0 +1,234 @@
+// Cargo features driven by .config expressions and values
+//
+// By default a member's feature is enabled when the symbol of the same name
+// is `y` or `m`. `[package.metadata.kbuild.features]` maps Kconfig
+// expressions (see expr.rs) to features instead, so features keep idiomatic
+// names and feature toggles of dependencies follow the configuration. A
+// symbol name mapped to a table selects features by the symbol's value,
+// which is how int and string symbols drive features:
+//
+//     [package.metadata.kbuild.features]
+//     "NET" = ["networking"]
+//     "LOGGING && !DEBUG" = ["log/release_max_level_info"]
+//     LOG_LEVEL = { "0" = ["log/max_level_off"], "1" = ["log/max_level_error"] }
+//     DEFAULT_SCHEDULER = { rr = ["sched_rr"] }
+//
+// Entries without a slash are features of the crate itself and are passed to
+// cargo as `pkg/feature`. `dep/feature` and `dep?/feature` entries are passed
+// as they are; cargo applies them to the selected packages depending on `dep`.
+
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::expr::{self, Tristate};
+use super::symbols::{parse_int, SymbolTable};
+use super::{CrateInfo, Workspace};
+
+/// An entry of a feature's list, in any of the forms cargo accepts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeatureEntry<'a> {
+    /// `name`: another feature of the package, or the implicit feature of
+    /// an optional dependency
+    Feature(&'a str),
+    /// `dep:name`: an optional dependency, without exposing a feature for it
+    Dependency(&'a str),
+    /// `dep/feature`, which also enables an optional `dep`, or the weak
+    /// `dep?/feature`, which only applies if `dep` is enabled otherwise
+    DependencyFeature { dep: &'a str, feature: &'a str, weak: bool },
+}
+
+impl<'a> FeatureEntry<'a> {
+    pub fn parse(entry: &'a str) -> FeatureEntry<'a> {
+        if let Some(dep) = entry.strip_prefix("dep:") {
+            return FeatureEntry::Dependency(dep);
+        }
+        match entry.split_once('/') {
+            Some((dep, feature)) => match dep.strip_suffix('?') {
+                Some(dep) => FeatureEntry::DependencyFeature { dep, feature, weak: true },
+                None => FeatureEntry::DependencyFeature { dep, feature, weak: false },
+            },
+            None => FeatureEntry::Feature(entry),
+        }
+    }
+}
+
+/// One entry of `[package.metadata.kbuild.features]`
+#[derive(Debug, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum FeatureRule {
+    /// Features enabled while the expression (the key) is not `n`
+    When(Vec<String>),
+    /// Features enabled per value of the symbol (the key)
+    Values(BTreeMap<String, Vec<String>>),
+}
+
+/// Whether a raw .config value equals a value written in a table
+///
+/// Numbers compare numerically (`0x10` matches `16`), strings unquoted.
+fn value_matches(raw: &str, value: &str) -> bool {
+    let raw = super::exports::unquote_string(raw).unwrap_or_else(|| raw.to_string());
+    match (parse_int(&raw), parse_int(value)) {
+        (Some(a), Some(b)) => a == b,
+        _ => raw == value,
+    }
+}
+
+/// Add entries in cargo's command-line form, skipping duplicates
+fn push_entries(features: &mut Vec<String>, crate_info: &CrateInfo, entries: &[String]) {
+    for entry in entries {
+        let feature = match FeatureEntry::parse(entry) {
+            FeatureEntry::Feature(name) => format!("{}/{}", crate_info.name, name),
+            _ => entry.clone(),
+        };
+        if !features.contains(&feature) {
+            features.push(feature);
+        }
+    }
+}
+
+/// Features a crate's table enables, in cargo's command-line form
+pub fn mapped(crate_info: &CrateInfo, config: &BTreeMap<String, String>) -> Result<Vec<String>, String> {
+    let mut features = Vec::new();
+
+    for (key, rule) in &crate_info.kbuild_features {
+        match rule {
+            FeatureRule::When(entries) => {
+                let value = expr::evaluate(key, config).map_err(|e| {
+                    format!(
+                        "Invalid expression '{}' in [package.metadata.kbuild.features] of '{}': {}",
+                        key, crate_info.name, e
+                    )
+                })?;
+                if value != Tristate::No {
+                    push_entries(&mut features, crate_info, entries);
+                }
+            }
+            FeatureRule::Values(values) => {
+                let raw = match config.get(key) {
+                    Some(raw) => raw,
+                    None => continue,
+                };
+                for (value, entries) in values {
+                    if value_matches(raw, value) {
+                        push_entries(&mut features, crate_info, entries);
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(features)
+}
+
+/// Check one list of features; problems are added to `errors`
+fn check_entries(
+    context: &str,
+    entries: &[String],
+    crate_info: &CrateInfo,
+    kbuild_packages: &BTreeSet<&str>,
+    errors: &mut Vec<String>,
+) {
+    for entry in entries {
+        match FeatureEntry::parse(entry) {
+            FeatureEntry::Dependency(_) => errors.push(format!(
+                "{}: '{}' cannot be enabled from the command line; declare a feature for it and list that instead",
+                context, entry
+            )),
+            FeatureEntry::DependencyFeature { dep, .. } => {
+                let package = crate_info.dependency_package(dep);
+                if kbuild_packages.contains(package) {
+                    errors.push(format!(
+                        "{}: '{}' is kbuild-enabled and reads .config itself; set its symbols in .config instead",
+                        context, package
+                    ));
+                }
+            }
+            FeatureEntry::Feature(name) => {
+                if !crate_info.features.contains_key(name) {
+                    errors.push(format!("{}: '{}' is not declared in [features]", context, name));
+                }
+            }
+        }
+    }
+}
+
+/// Check the feature tables of all workspace members
+///
+/// Expressions must parse and only refer to known symbols, and value tables
+/// must list values their int or string symbol can take. Entries must name
+/// a declared feature of the crate, or a feature of a dependency that is not
+/// kbuild-enabled (such a dependency reads .config itself).
+pub fn check(
+    workspace: &Workspace,
+    symbol_table: &SymbolTable,
+    config: &BTreeMap<String, String>,
+) -> Result<(), String> {
+    let kbuild_packages: BTreeSet<&str> = workspace.kbuild_crates().map(|c| c.name.as_str()).collect();
+    let mut errors = Vec::new();
+
+    for crate_info in &workspace.crates {
+        for (key, rule) in &crate_info.kbuild_features {
+            match rule {
+                FeatureRule::When(entries) => {
+                    let context = format!("'{}' in '{}'", key, crate_info.name);
+                    if let Err(e) = expr::evaluate(key, &BTreeMap::new()) {
+                        errors.push(format!("{}: {}", context, e));
+                        continue;
+                    }
+                    for symbol in expr::symbols(key) {
+                        if !symbol_table.contains(&symbol) {
+                            errors.push(format!(
+                                "{}: unknown symbol '{}' (a string constant needs quotes: \"{}\")",
+                                context, symbol, symbol
+                            ));
+                        }
+                    }
+                    check_entries(&context, entries, crate_info, &kbuild_packages, &mut errors);
+                }
+                FeatureRule::Values(values) => {
+                    for (value, entries) in values {
+                        let context = format!("{} = \"{}\" in '{}'", key, value, crate_info.name);
+                        if let Err(e) = symbol_table.check_value(key, value, config) {
+                            errors.push(format!("{}: {}", context, e));
+                            continue;
+                        }
+                        check_entries(&context, entries, crate_info, &kbuild_packages, &mut errors);
+                    }
+                }
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("Invalid [package.metadata.kbuild.features]:\n  - {}", errors.join("\n  - ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_feature_entries() {
+        assert_eq!(FeatureEntry::parse("networking"), FeatureEntry::Feature("networking"));
+        assert_eq!(FeatureEntry::parse("dep:serde"), FeatureEntry::Dependency("serde"));
+        assert_eq!(
+            FeatureEntry::parse("log/std"),
+            FeatureEntry::DependencyFeature { dep: "log", feature: "std", weak: false }
+        );
+        assert_eq!(
+            FeatureEntry::parse("log?/std"),
+            FeatureEntry::DependencyFeature { dep: "log", feature: "std", weak: true }
+        );
+    }
+
+    #[test]
+    fn value_matches_numbers_and_strings() {
+        assert!(value_matches("0x10", "16"));
+        assert!(value_matches("\"rr\"", "rr"));
+        assert!(!value_matches("\"rr\"", "cfs"));
+        assert!(!value_matches("3", "4"));
+    }
+}
@@ -0,0 +1,88 @@
+// fixdep-style symbol tracking for fine-grained rebuilds
+//
+// Linux's scripts/basic/fixdep records which CONFIG_ symbols each object
+// file uses, so changing one symbol recompiles only the files that use it.
+// Here the unit is a crate: its sources are scanned for symbol names, and
+// the rustc wrapper passes each crate only the cfgs of the symbols it uses,
+// from a per-crate flags file. Those files are rewritten only when their
+// content changes, so toggling a symbol rebuilds exactly the crates that
+// reference it.
+//
+// Scanning is deliberately conservative: any identifier equal to a symbol
+// name (or NAME_MODULE) counts as a use, in cfg attributes, cfg! macros and
+// constants alike. A symbol referenced only through a macro defined in
+// another crate is not seen; list it in `[package.metadata.kbuild] symbols`.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+/// Directories and files of a crate that may contain Rust code
+const SOURCE_ROOTS: &[&str] = &["src", "build.rs", "tests", "examples", "benches"];
+
+/// Symbols from `known` referenced by the crate in `crate_dir`
+pub fn used_symbols(crate_dir: &Path, known: &BTreeSet<String>) -> Result<BTreeSet<String>, String> {
+    let mut used = BTreeSet::new();
+
+    for root in SOURCE_ROOTS {
+        let path = crate_dir.join(root);
+        if path.exists() {
+            scan_path(&path, known, &mut used)?;
+        }
+    }
+
+    Ok(used)
+}
+
+fn scan_path(path: &Path, known: &BTreeSet<String>, used: &mut BTreeSet<String>) -> Result<(), String> {
+    if path.is_dir() {
+        let mut entries: Vec<_> = fs::read_dir(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .collect();
+        entries.sort();
+        for entry in entries {
+            scan_path(&entry, known, used)?;
+        }
+    } else if path.extension().is_some_and(|ext| ext == "rs") {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        scan_source(&content, known, used);
+    }
+    Ok(())
+}
+
+/// Record every identifier in `source` that names a known symbol
+fn scan_source(source: &str, known: &BTreeSet<String>, used: &mut BTreeSet<String>) {
+    let identifiers = source
+        .split(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .filter(|word| !word.is_empty());
+
+    for word in identifiers {
+        if known.contains(word) {
+            used.insert(word.to_string());
+        } else if let Some(name) = word.strip_suffix("_MODULE") {
+            if known.contains(name) {
+                used.insert(name.to_string());
+            }
+        }
+    }
+}
+
+/// Per-crate symbol usage, written to target/kbuild/symbol-usage.txt
+pub fn render_usage(usage: &BTreeMap<String, BTreeSet<String>>) -> String {
+    let mut content = String::new();
+    content.push_str("# Auto-generated by cargo-kbuild: symbols referenced by each kbuild crate\n");
+    content.push_str("# Only changes to these symbols rebuild the crate\n");
+    for (crate_name, symbols) in usage {
+        let symbols: Vec<&str> = symbols.iter().map(|s| s.as_str()).collect();
+        content.push_str(&format!("{}: {}\n", crate_name, symbols.join(" ")));
+    }
+    content
+}
+
+/// Number of packages in Cargo.lock, i.e. the full dependency graph
+pub fn locked_package_count(workspace_root: &Path) -> Option<usize> {
+    let content = fs::read_to_string(workspace_root.join("Cargo.lock")).ok()?;
+    Some(content.lines().filter(|line| line.trim() == "[[package]]").count())
+}
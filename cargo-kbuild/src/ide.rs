@@ -0,0 +1,148 @@
+// rust-analyzer settings matching the current .config
+//
+// rust-analyzer runs plain cargo, so on its own it sees no kbuild cfgs:
+// `#[cfg(SMP)]` code shows as inactive and `kbuild_config` has no config.rs
+// until cargo-kbuild ran once. `cargo kbuild ide-setup` generates the config
+// files and writes .vscode/settings.json with:
+//
+// * `rust-analyzer.cargo.cfgs`: the cfgs of the current .config
+// * `rust-analyzer.cargo.features`: the features cargo-kbuild enables
+// * `rust-analyzer.cargo.extraEnv`: KBUILD_CONFIG and friends, so build
+//   scripts using kbuild_build apply the same .config
+// * `rust-analyzer.check.overrideCommand`: `cargo kbuild check --workspace`, so
+//   diagnostics come from the build cargo-kbuild produces
+//
+// Other settings in the file are kept. Once the file holds kbuild settings,
+// every cargo-kbuild run refreshes them, so they follow .config changes.
+
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Settings file, relative to the workspace root
+const SETTINGS_FILE: &str = ".vscode/settings.json";
+
+/// rust-analyzer's default `cargo.cfgs`, which setting the key replaces
+const DEFAULT_CFGS: &[&str] = &["debug_assertions", "miri"];
+
+/// Marker telling refreshes that the settings file is managed by cargo-kbuild
+const MARKER_ENV: &str = "KBUILD_CONFIG";
+
+/// rust-analyzer's form of a symbol's cfg (`SMP`, `NET_MODULE`, `LOG_LEVEL=3`)
+fn ra_cfg(name: &str, raw: &str) -> Option<String> {
+    match raw {
+        "y" => Some(name.to_string()),
+        "m" => Some(super::symbols::module_cfg(name)),
+        "n" => None,
+        _ => {
+            let value = super::exports::unquote_string(raw).unwrap_or_else(|| raw.to_string());
+            Some(format!("{}={}", name, value))
+        }
+    }
+}
+
+/// The kbuild entries of the settings file
+///
+/// `env` is the environment cargo-kbuild gives cargo (see `kbuild_env`).
+pub fn kbuild_settings(
+    config_path: &Path,
+    config: &BTreeMap<String, String>,
+    features: &[String],
+    env: &[(&str, PathBuf)],
+) -> Result<Map<String, Value>, String> {
+    let mut cfgs: Vec<String> = DEFAULT_CFGS.iter().map(|c| c.to_string()).collect();
+    cfgs.extend(config.iter().filter_map(|(name, raw)| ra_cfg(name, raw)));
+
+    let extra_env: Map<String, Value> = env
+        .iter()
+        .map(|(name, value)| (name.to_string(), Value::String(value.display().to_string())))
+        .collect();
+
+    let exe = env::current_exe()
+        .map_err(|e| format!("Failed to locate cargo-kbuild executable: {}", e))?;
+    let check_command = vec![
+        exe.display().to_string(),
+        "kbuild".to_string(),
+        "--kconfig".to_string(),
+        config_path.display().to_string(),
+        "check".to_string(),
+        "--workspace".to_string(),
+        "--message-format=json".to_string(),
+        "--all-targets".to_string(),
+    ];
+
+    let mut settings = Map::new();
+    settings.insert("rust-analyzer.cargo.cfgs".to_string(), json!(cfgs));
+    settings.insert("rust-analyzer.cargo.features".to_string(), json!(features));
+    settings.insert("rust-analyzer.cargo.extraEnv".to_string(), Value::Object(extra_env));
+    // rust-analyzer's own cargo runs go to target/rust-analyzer: they do not
+    // use the rustc wrapper, and sharing target/ would make the two
+    // invalidate each other's artifacts
+    settings.insert("rust-analyzer.cargo.targetDir".to_string(), json!(true));
+    settings.insert("rust-analyzer.check.overrideCommand".to_string(), json!(check_command));
+    Ok(settings)
+}
+
+/// Read the settings file; a missing file is empty
+fn read_settings(path: &Path) -> Result<Option<Map<String, Value>>, String> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(_) => return Ok(None),
+    };
+    if content.trim().is_empty() {
+        return Ok(Some(Map::new()));
+    }
+
+    match serde_json::from_str(&content) {
+        Ok(Value::Object(settings)) => Ok(Some(settings)),
+        Ok(_) => Err(format!("{} is not a JSON object", path.display())),
+        Err(e) => Err(format!(
+            "Failed to parse {} ({}); comments and trailing commas are not supported, add the rust-analyzer settings by hand",
+            path.display(),
+            e
+        )),
+    }
+}
+
+/// Whether the settings were written by `cargo kbuild ide-setup`
+fn is_managed(settings: &Map<String, Value>) -> bool {
+    settings
+        .get("rust-analyzer.cargo.extraEnv")
+        .and_then(|env| env.get(MARKER_ENV))
+        .is_some()
+}
+
+/// Merge the kbuild entries into the settings file, keeping everything else
+///
+/// Returns whether the file changed.
+pub fn write_settings(workspace_root: &Path, kbuild: Map<String, Value>) -> Result<bool, String> {
+    let path = workspace_root.join(SETTINGS_FILE);
+    let mut settings = read_settings(&path)?.unwrap_or_default();
+    settings.extend(kbuild);
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+    let mut content = serde_json::to_string_pretty(&Value::Object(settings))
+        .map_err(|e| format!("Failed to serialize {}: {}", path.display(), e))?;
+    content.push('\n');
+    super::write_if_changed(&path, &content)
+}
+
+/// Update the settings file if `cargo kbuild ide-setup` created it
+///
+/// Returns whether the file changed. A file that cannot be parsed is left
+/// alone with a warning, since the build itself does not depend on it.
+pub fn refresh_settings(workspace_root: &Path, kbuild: Map<String, Value>) -> Result<bool, String> {
+    let path = workspace_root.join(SETTINGS_FILE);
+    match read_settings(&path) {
+        Ok(Some(settings)) if is_managed(&settings) => write_settings(workspace_root, kbuild),
+        Ok(_) => Ok(false),
+        Err(e) => {
+            println!("⚠️  Not refreshing rust-analyzer settings: {}", e);
+            Ok(false)
+        }
+    }
+}
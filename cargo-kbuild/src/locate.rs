@@ -0,0 +1,117 @@
+// Locating the workspace and the packages a command applies to
+//
+// Like cargo, cargo-kbuild can be run from any directory of the workspace:
+// the workspace root is found by walking up from the current directory (or
+// from `--manifest-path`), and the packages a command selects follow cargo's
+// rules: `-p`/`--package`, `--workspace`, otherwise the package the current
+// directory belongs to, otherwise the default members.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// Resolve `path` against `base` unless it is absolute
+pub fn absolute(base: &Path, path: &Path) -> PathBuf {
+    if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        base.join(path)
+    }
+}
+
+/// Root directory of the workspace containing `cwd` (or `manifest_path`)
+///
+/// Asks `cargo locate-project --workspace`; if that fails, walks up to the
+/// nearest Cargo.toml and from there to the first one with a `[workspace]`.
+pub fn workspace_root(cwd: &Path, manifest_path: Option<&Path>) -> Result<PathBuf, String> {
+    let mut cmd = process::Command::new("cargo");
+    cmd.args(["locate-project", "--workspace", "--message-format", "plain"]);
+    if let Some(manifest_path) = manifest_path {
+        cmd.arg("--manifest-path").arg(manifest_path);
+    }
+    cmd.current_dir(cwd);
+
+    if let Ok(output) = cmd.output() {
+        if output.status.success() {
+            let manifest = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            if let Some(root) = Path::new(&manifest).parent() {
+                return Ok(root.to_path_buf());
+            }
+        }
+    }
+
+    let package_manifest = match manifest_path {
+        Some(path) => path.to_path_buf(),
+        None => nearest_manifest(cwd)
+            .ok_or_else(|| format!("could not find Cargo.toml in {} or any parent directory", cwd.display()))?,
+    };
+    let package_dir = package_manifest.parent().unwrap_or(cwd);
+
+    let mut dir = Some(package_dir);
+    while let Some(current) = dir {
+        if declares_workspace(&current.join("Cargo.toml")) {
+            return Ok(current.to_path_buf());
+        }
+        dir = current.parent();
+    }
+    Ok(package_dir.to_path_buf())
+}
+
+/// The first Cargo.toml in `dir` or its parents
+pub fn nearest_manifest(dir: &Path) -> Option<PathBuf> {
+    let mut dir = Some(dir);
+    while let Some(current) = dir {
+        let candidate = current.join("Cargo.toml");
+        if candidate.is_file() {
+            return Some(candidate);
+        }
+        dir = current.parent();
+    }
+    None
+}
+
+fn declares_workspace(manifest: &Path) -> bool {
+    fs::read_to_string(manifest)
+        .ok()
+        .and_then(|content| content.parse::<toml::Table>().ok())
+        .is_some_and(|table| table.contains_key("workspace"))
+}
+
+/// Package selection options found in the arguments passed on to cargo
+#[derive(Debug, Default)]
+pub struct CargoSelection {
+    /// `-p`/`--package` values
+    pub packages: Vec<String>,
+    /// `--workspace` (or its deprecated alias `--all`)
+    pub workspace: bool,
+    /// `--manifest-path`
+    pub manifest_path: Option<PathBuf>,
+}
+
+impl CargoSelection {
+    /// Scan cargo arguments, stopping at `--` (the rest belongs to the program)
+    pub fn from_args(args: &[String]) -> CargoSelection {
+        let mut selection = CargoSelection::default();
+        let mut iter = args.iter();
+
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--" => break,
+                "--workspace" | "--all" => selection.workspace = true,
+                "-p" | "--package" => selection.packages.extend(iter.next().cloned()),
+                "--manifest-path" => selection.manifest_path = iter.next().map(PathBuf::from),
+                _ => {
+                    if let Some(package) = arg.strip_prefix("--package=") {
+                        selection.packages.push(package.to_string());
+                    } else if let Some(package) = arg.strip_prefix("-p").filter(|p| !p.is_empty()) {
+                        selection.packages.push(package.to_string());
+                    } else if let Some(path) = arg.strip_prefix("--manifest-path=") {
+                        selection.manifest_path = Some(PathBuf::from(path));
+                    }
+                }
+            }
+        }
+
+        selection
+    }
+}
@@ -1,20 +1,49 @@
 use clap::{Args, Parser, Subcommand};
 use serde::Deserialize;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::process;
 use std::env;
 
+mod exports;
+mod features;
+mod fixdep;
+mod ide;
+mod locate;
+mod manifest;
+mod metadata;
+mod rust_project;
+mod rustflags;
+mod wrapper;
+
+use kbuild_build::render::{render_constant, TRISTATE_DEFINITION};
+use kbuild_build::workspace::SymbolSources;
+use kbuild_build::{display_path, dotconfig, expr, symbols};
+
 #[derive(Debug, Deserialize)]
 struct CargoToml {
     package: Package,
     #[serde(default)]
-    features: HashMap<String, Vec<String>>,
-    // Note: dependencies field kept for potential future feature validation
+    features: BTreeMap<String, Vec<String>>,
     #[serde(default)]
-    #[allow(dead_code)]
-    dependencies: HashMap<String, toml::Value>,
+    dependencies: BTreeMap<String, toml::Value>,
+    #[serde(default, rename = "build-dependencies")]
+    build_dependencies: BTreeMap<String, toml::Value>,
+}
+
+impl CargoToml {
+    /// Dependency names as used in `[features]`, with the package each names
+    fn dependency_packages(&self) -> BTreeMap<String, String> {
+        self.dependencies
+            .iter()
+            .chain(&self.build_dependencies)
+            .map(|(name, spec)| {
+                let package = spec.get("package").and_then(|p| p.as_str()).unwrap_or(name);
+                (name.clone(), package.to_string())
+            })
+            .collect()
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -32,40 +61,215 @@ struct Metadata {
 
 #[derive(Debug, Deserialize, Default)]
 struct KbuildMetadata {
+    /// `true` for kbuild crates, `false` to mark a legacy crate explicitly
+    #[serde(default)]
+    enabled: Option<bool>,
+    /// Symbols this crate reads; enables a per-crate config module
+    #[serde(default)]
+    symbols: Option<Vec<String>>,
+    /// Symbol expression or value -> features it enables (see features.rs)
+    #[serde(default)]
+    features: BTreeMap<String, features::FeatureRule>,
+}
+
+/// How `--cfg` flags are passed to rustc
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum CfgMode {
+    /// cargo-kbuild acts as RUSTC_WRAPPER and adds the flags only for
+    /// kbuild-enabled crates
+    #[default]
+    Wrapper,
+    /// Flags are added to the rustflags of every crate in the graph
+    Rustflags,
+}
+
+/// Workspace-wide settings from `[workspace.metadata.kbuild]`
+#[derive(Debug, Deserialize, Default)]
+struct WorkspaceKbuildMetadata {
+    /// Extra outputs written next to config.rs (e.g. "config.json", "auto.conf")
     #[serde(default)]
-    enabled: bool,
+    outputs: Option<Vec<String>>,
+    /// How `--cfg` flags reach rustc ("wrapper" or "rustflags")
+    #[serde(default, rename = "cfg-mode")]
+    cfg_mode: CfgMode,
+    /// Kconfig, defconfigs and declared symbol values
+    #[serde(flatten)]
+    sources: SymbolSources,
+    /// Migration aid: treat members that declare features but do not set
+    /// `enabled` as kbuild crates, as older cargo-kbuild versions did
+    #[serde(default, rename = "features-imply-kbuild")]
+    features_imply_kbuild: bool,
+}
+
+impl WorkspaceKbuildMetadata {
+    /// Resolve the selected outputs, falling back to the defaults
+    fn outputs(&self) -> Result<Vec<exports::Output>, String> {
+        match &self.outputs {
+            Some(names) => names.iter().map(|n| exports::Output::from_name(n)).collect(),
+            None => Ok(exports::Output::DEFAULT.to_vec()),
+        }
+    }
+
 }
 
 #[derive(Debug)]
 struct CrateInfo {
+    /// Package ID from `cargo metadata` (the name when read from Cargo.toml)
+    id: String,
     name: String,
-    // Note: path field kept for potential future features (e.g., detailed error reporting)
-    #[allow(dead_code)]
+    /// Directory containing the crate's Cargo.toml
     path: PathBuf,
     has_kbuild: bool,
-    features: HashMap<String, Vec<String>>,
+    /// Whether `[package.metadata.kbuild] enabled` is set at all
+    declares_kbuild: bool,
+    features: BTreeMap<String, Vec<String>>,
+    symbols: Option<Vec<String>>,
+    /// `[package.metadata.kbuild.features]`
+    kbuild_features: BTreeMap<String, features::FeatureRule>,
+    /// Dependency name as used in `[features]` -> package it refers to
+    dependencies: BTreeMap<String, String>,
 }
 
 impl CrateInfo {
-    fn is_kbuild_enabled(&self) -> bool {
-        // A crate is kbuild-enabled if metadata.kbuild.enabled is set
-        // or if it has any features (since non-kbuild crates typically don't declare features)
-        // This is a heuristic that works for the current codebase
-        self.has_kbuild || !self.features.is_empty()
+    /// Package a dependency name in `[features]` refers to (renames resolved)
+    fn dependency_package<'a>(&'a self, dep: &'a str) -> &'a str {
+        self.dependencies.get(dep).map(|p| p.as_str()).unwrap_or(dep)
+    }
+    
+    /// Whether older versions guessed this crate to be kbuild-enabled
+    /// (it declares features) without its metadata saying either way
+    fn classified_by_features(&self) -> bool {
+        !self.declares_kbuild && !self.features.is_empty()
     }
 }
 
+/// How a package takes part in the build, decided by its metadata alone
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CrateKind {
+    /// `[package.metadata.kbuild] enabled = true`: reads .config itself
+    Kbuild,
+    /// A workspace member that is not kbuild-enabled
+    Legacy,
+    /// A package outside the workspace that is not kbuild-enabled
+    ThirdParty,
+}
+
 #[derive(Debug)]
 struct Workspace {
     // Note: root field kept for potential future features (e.g., relative path resolution)
     #[allow(dead_code)]
     root: PathBuf,
+    /// Workspace members
     crates: Vec<CrateInfo>,
+    /// Names of the members built when no package is selected
+    default_members: BTreeSet<String>,
+    /// Every other package in the resolved graph (path, git and registry dependencies)
+    external: Vec<CrateInfo>,
+    metadata: WorkspaceKbuildMetadata,
+    /// Whether the workspace was discovered through `cargo metadata`
+    from_cargo_metadata: bool,
 }
 
 impl Workspace {
-    fn new(root: PathBuf) -> Result<Self, String> {
+    /// Discover the workspace through `cargo metadata`, falling back to
+    /// reading the Cargo.toml files directly if cargo cannot resolve it
+    ///
+    /// With `strict`, members whose Cargo.toml cannot be read fail the
+    /// build instead of being reported and skipped.
+    fn new(root: PathBuf, strict: bool) -> Result<Self, String> {
+        match metadata::load(&root, &[]) {
+            Ok(cargo_metadata) => Self::from_cargo_metadata(root, cargo_metadata),
+            Err(e) => {
+                println!("⚠️  {}", e);
+                println!("   Reading Cargo.toml files directly (glob members, exclude and the dependency graph are not seen)\n");
+                Self::from_manifests(root, strict)
+            }
+        }
+    }
+    
+    fn from_cargo_metadata(root: PathBuf, cargo_metadata: metadata::CargoMetadata) -> Result<Self, String> {
+        let metadata = match cargo_metadata.metadata.as_ref().and_then(|m| m.get("kbuild")) {
+            Some(kbuild) => serde_json::from_value(kbuild.clone())
+                .map_err(|e| format!("Invalid [workspace.metadata.kbuild]: {}", e))?,
+            None => WorkspaceKbuildMetadata::default(),
+        };
+        
+        let members: BTreeSet<&String> = cargo_metadata.workspace_members.iter().collect();
+        let default_ids: BTreeSet<&String> = match &cargo_metadata.workspace_default_members {
+            Some(ids) => ids.iter().collect(),
+            None => members.clone(),
+        };
+        
         let mut crates = Vec::new();
+        let mut external = Vec::new();
+        let mut default_members = BTreeSet::new();
+        for package in &cargo_metadata.packages {
+            let crate_info = Self::package_crate(package);
+            if default_ids.contains(&package.id) {
+                default_members.insert(crate_info.name.clone());
+            }
+            if members.contains(&package.id) {
+                crates.push(crate_info);
+            } else {
+                external.push(crate_info);
+            }
+        }
+        
+        Ok(Workspace { root, crates, default_members, external, metadata, from_cargo_metadata: true })
+    }
+    
+    /// A package from `cargo metadata`
+    ///
+    /// A package whose `[package.metadata.kbuild]` cannot be read (often a
+    /// dependency using the table for something else) is reported and
+    /// treated as a third-party crate rather than failing the build.
+    fn package_crate(package: &metadata::Package) -> CrateInfo {
+        let kbuild: KbuildMetadata = match package.metadata.as_ref().and_then(|m| m.get("kbuild")) {
+            Some(kbuild) => serde_json::from_value(kbuild.clone()).unwrap_or_else(|e| {
+                println!(
+                    "⚠️  Ignoring invalid [package.metadata.kbuild] in {}: {}",
+                    package.manifest_path.display(),
+                    e
+                );
+                println!("   {} is treated as a third-party crate\n", package.name);
+                KbuildMetadata::default()
+            }),
+            None => KbuildMetadata::default(),
+        };
+        
+        CrateInfo {
+            id: package.id.clone(),
+            name: package.name.clone(),
+            path: package.manifest_path.parent().unwrap_or(Path::new(".")).to_path_buf(),
+            has_kbuild: kbuild.enabled == Some(true),
+            declares_kbuild: kbuild.enabled.is_some(),
+            features: package.features.clone(),
+            symbols: kbuild.symbols,
+            kbuild_features: kbuild.features,
+            dependencies: package.dependencies.iter()
+                .map(|d| (d.rename.clone().unwrap_or_else(|| d.name.clone()), d.name.clone()))
+                .collect(),
+        }
+    }
+    
+    /// One-line summary of the discovered packages
+    fn describe(&self) -> String {
+        let mut summary = format!(
+            "{} crate(s), {} built by default",
+            self.crates.len(),
+            self.crates.iter().filter(|c| self.default_members.contains(&c.name)).count()
+        );
+        if self.from_cargo_metadata {
+            summary.push_str(&format!(", {} dependency package(s)", self.external.len()));
+        }
+        summary
+    }
+    
+    fn from_manifests(root: PathBuf, strict: bool) -> Result<Self, String> {
+        let mut crates = Vec::new();
+        // Members that could not be read, with the reason
+        let mut skipped = Vec::new();
         
         // Read workspace Cargo.toml
         let workspace_toml_path = root.join("Cargo.toml");
@@ -75,10 +279,22 @@ impl Workspace {
         let workspace_toml: toml::Value = toml::from_str(&workspace_toml_content)
             .map_err(|e| format!("Failed to parse workspace Cargo.toml: {}", e))?;
         
+        // Read [workspace.metadata.kbuild] settings
+        let metadata = match workspace_toml
+            .get("workspace")
+            .and_then(|w| w.get("metadata"))
+            .and_then(|m| m.get("kbuild"))
+        {
+            Some(kbuild) => kbuild.clone().try_into()
+                .map_err(|e| format!("Invalid [workspace.metadata.kbuild]: {}", e))?,
+            None => WorkspaceKbuildMetadata::default(),
+        };
+        
         // Parse root package if it exists
         if workspace_toml.get("package").is_some() {
-            if let Ok(root_crate) = Self::parse_crate(&root) {
-                crates.push(root_crate);
+            match Self::parse_crate(&root, &workspace_toml, &root) {
+                Ok(root_crate) => crates.push(root_crate),
+                Err(e) => skipped.push((".".to_string(), e)),
             }
         }
         
@@ -93,120 +309,232 @@ impl Workspace {
         for member in members {
             let member_path = member.as_str().ok_or("Invalid member path")?;
             let crate_path = root.join(member_path);
+            // The root package, listed as member "."
+            if crate_path == root && workspace_toml.get("package").is_some() {
+                continue;
+            }
             
-            if let Ok(crate_info) = Self::parse_crate(&crate_path) {
-                crates.push(crate_info);
+            match Self::parse_crate(&crate_path, &workspace_toml, &root) {
+                Ok(crate_info) => crates.push(crate_info),
+                Err(e) => skipped.push((member_path.to_string(), e)),
+            }
+        }
+        
+        if !skipped.is_empty() {
+            let list: Vec<String> = skipped.iter()
+                .map(|(member, e)| format!("  - {}: {}", member, e.trim_end()))
+                .collect();
+            if strict {
+                return Err(format!(
+                    "{} workspace member(s) could not be read:\n{}",
+                    skipped.len(),
+                    list.join("\n")
+                ));
             }
+            println!("⚠️  Skipped {} workspace member(s) whose Cargo.toml could not be read:", skipped.len());
+            for line in &list {
+                println!("{}", line);
+            }
+            println!("   Their features are neither validated nor enabled; use --strict to fail instead\n");
         }
         
-        Ok(Workspace { root, crates })
+        // Like cargo: only the root package is built by default, if there is one.
+        // It is the crate whose manifest is the root Cargo.toml (which may also
+        // be listed as member "."), not necessarily the first one read
+        let default_members = if workspace_toml.get("package").is_some() {
+            crates.iter()
+                .filter(|c| c.path == root)
+                .map(|c| c.name.clone())
+                .collect()
+        } else {
+            crates.iter().map(|c| c.name.clone()).collect()
+        };
+        Ok(Workspace {
+            root,
+            crates,
+            default_members,
+            external: Vec::new(),
+            metadata,
+            from_cargo_metadata: false,
+        })
     }
     
-    fn parse_crate(crate_path: &Path) -> Result<CrateInfo, String> {
+    /// Read a member's Cargo.toml, resolving fields inherited from the workspace
+    fn parse_crate(crate_path: &Path, workspace_toml: &toml::Value, root: &Path) -> Result<CrateInfo, String> {
         let cargo_toml_path = crate_path.join("Cargo.toml");
-        let cargo_toml_content = fs::read_to_string(&cargo_toml_path)
-            .map_err(|e| format!("Failed to read {}: {}", cargo_toml_path.display(), e))?;
+        let manifest = manifest::read(&cargo_toml_path, workspace_toml, root)?;
         
-        let cargo_toml: CargoToml = toml::from_str(&cargo_toml_content)
+        let cargo_toml: CargoToml = manifest.try_into()
             .map_err(|e| format!("Failed to parse {}: {}", cargo_toml_path.display(), e))?;
         
+        let dependencies = cargo_toml.dependency_packages();
         Ok(CrateInfo {
+            id: cargo_toml.package.name.clone(),
             name: cargo_toml.package.name.clone(),
             path: crate_path.to_path_buf(),
-            has_kbuild: cargo_toml.package.metadata.kbuild.enabled,
+            has_kbuild: cargo_toml.package.metadata.kbuild.enabled == Some(true),
+            declares_kbuild: cargo_toml.package.metadata.kbuild.enabled.is_some(),
             features: cargo_toml.features,
+            symbols: cargo_toml.package.metadata.kbuild.symbols,
+            kbuild_features: cargo_toml.package.metadata.kbuild.features,
+            dependencies,
         })
     }
     
-    // Note: find_crate method kept for potential future features (e.g., dependency graph analysis)
-    #[allow(dead_code)]
+    /// Whether a package, member or not, is kbuild-enabled
+    ///
+    /// Only `[package.metadata.kbuild] enabled` counts, unless the workspace
+    /// opted into `features-imply-kbuild` while migrating (members only).
+    fn is_kbuild_member(&self, crate_info: &CrateInfo) -> bool {
+        if crate_info.has_kbuild {
+            return true;
+        }
+        self.metadata.features_imply_kbuild
+            && crate_info.classified_by_features()
+            && self.crates.iter().any(|c| c.id == crate_info.id)
+    }
+    
+    /// Classify a package of the graph by name
+    fn kind(&self, package_name: &str) -> CrateKind {
+        if let Some(member) = self.find_crate(package_name) {
+            return if self.is_kbuild_member(member) { CrateKind::Kbuild } else { CrateKind::Legacy };
+        }
+        if self.external_kbuild_crates().any(|c| c.name == package_name) {
+            CrateKind::Kbuild
+        } else {
+            CrateKind::ThirdParty
+        }
+    }
+    
+    /// kbuild-enabled members and packages outside the workspace
+    fn kbuild_crates(&self) -> impl Iterator<Item = &CrateInfo> {
+        self.crates.iter().filter(|c| self.is_kbuild_member(c)).chain(self.external_kbuild_crates())
+    }
+    
+    /// Packages outside the workspace (path, git or registry dependencies)
+    /// that opted in with `[package.metadata.kbuild] enabled`
+    fn external_kbuild_crates(&self) -> impl Iterator<Item = &CrateInfo> {
+        self.external.iter().filter(|c| self.is_kbuild_member(c))
+    }
+    
     fn find_crate(&self, name: &str) -> Option<&CrateInfo> {
         self.crates.iter().find(|c| c.name == name)
     }
 }
 
-/// Check if a dependency package supports kbuild
-/// Note: Function kept for potential future validation features
-#[allow(dead_code)]
-fn is_dependency_kbuild_enabled(workspace: &Workspace, pkg_name: &str) -> bool {
-    if let Some(dep_crate) = workspace.find_crate(pkg_name) {
-        // Check metadata.kbuild.enabled - this is the primary indicator
-        if dep_crate.has_kbuild {
-            return true;
-        }
-        
-        // As a fallback heuristic, check if it has any features
-        // (works for current codebase where non-kbuild crates don't use features)
-        if !dep_crate.features.is_empty() {
-            return true;
-        }
+/// Report members the old heuristic would classify differently
+///
+/// Older versions treated every crate declaring features as kbuild-enabled.
+/// Members that declare features without setting `enabled` either way are
+/// listed, so the change (or the migration flag keeping the old rule) is seen.
+/// Members with features and `enabled = false` were kbuild crates then too.
+fn report_classification(workspace: &Workspace) {
+    let opted_out: Vec<&str> = workspace.crates.iter()
+        .filter(|c| c.declares_kbuild && !c.has_kbuild && !c.features.is_empty())
+        .map(|c| c.name.as_str())
+        .collect();
+    if !opted_out.is_empty() {
+        println!(
+            "ℹ️  {} crate(s) with features are legacy crates as they set enabled = false \
+             (guessed to be kbuild crates before): {}\n",
+            opted_out.len(),
+            opted_out.join(", ")
+        );
+    }
+    
+    let guessed: Vec<&str> = workspace.crates.iter()
+        .filter(|c| c.classified_by_features())
+        .map(|c| c.name.as_str())
+        .collect();
+    if guessed.is_empty() {
+        return;
     }
     
-    false
+    if workspace.metadata.features_imply_kbuild {
+        println!(
+            "⚠️  features-imply-kbuild: treating {} crate(s) with features as kbuild-enabled: {}",
+            guessed.len(),
+            guessed.join(", ")
+        );
+        println!("   Set [package.metadata.kbuild] enabled = true or false in each, then remove the setting\n");
+    } else {
+        println!(
+            "⚠️  {} crate(s) declare features but not [package.metadata.kbuild] enabled; \
+             they are legacy crates now, not kbuild crates as guessed before: {}",
+            guessed.len(),
+            guessed.join(", ")
+        );
+        println!("   Set enabled = true or false in each to confirm, or features-imply-kbuild = true");
+        println!("   in [workspace.metadata.kbuild] to keep the old classification while migrating\n");
+    }
 }
 
 /// Validate features for all kbuild-enabled crates
 fn validate_features(workspace: &Workspace) -> Result<(), String> {
     println!("🔍 Validating feature dependencies...\n");
+    report_classification(workspace);
     
-    // 1. Build a set of kbuild-enabled packages for performance
-    let kbuild_packages: HashSet<String> = workspace
-        .crates
-        .iter()
-        .filter(|c| c.is_kbuild_enabled())
-        .map(|c| c.name.clone())
-        .collect();
-    
-    // 2. Build a set of all workspace packages
-    let workspace_packages: HashSet<String> = workspace
-        .crates
-        .iter()
-        .map(|c| c.name.clone())
-        .collect();
-    
-    // 3. Validate each kbuild-enabled crate's features
-    for crate_info in workspace.crates.iter().filter(|c| c.is_kbuild_enabled()) {
-        for (feature_name, deps) in &crate_info.features {
-            for dep in deps {
-                // Check if sub-feature is specified
-                if let Some((pkg_name, sub_feature)) = dep.split_once('/') {
-                    // Key decision: Does the dependency support kbuild?
-                    if kbuild_packages.contains(pkg_name) {
-                        // ❌ Error: kbuild-enabled workspace crate cannot specify sub-feature
-                        return Err(format!(
-                            "❌ Error in crate '{}':\n\
-                             \n\
-                             Feature '{}' specifies sub-feature: '{}'\n\
-                             \n\
-                             Dependency '{}' is kbuild-enabled:\n\
-                             - It reads CONFIG_* from .config directly\n\
-                             - Cannot be controlled by parent crate\n\
-                             \n\
-                             Solution:\n\
-                             1. Change to: {} = [\"{}\"]\n\
-                             2. Enable {} in .config file\n\
-                             \n\
-                             Note: Third-party crates (e.g., log/std, tokio/rt) are allowed sub-features.\n",
-                            crate_info.name,
-                            feature_name,
-                            dep,
-                            pkg_name,
-                            feature_name, pkg_name,
-                            sub_feature
-                        ));
-                    } else if workspace_packages.contains(pkg_name) {
-                        // ℹ️ Info: Non-kbuild workspace crate - sub-feature allowed
-                        eprintln!(
-                            "ℹ️  '{}' is not kbuild-enabled, sub-feature allowed: {}\n",
-                            pkg_name, dep
-                        );
+    for crate_info in workspace.kbuild_crates() {
+        for (feature_name, entries) in &crate_info.features {
+            for entry in entries {
+                // Only `dep/feature` and `dep?/feature` reach into a dependency;
+                // `feature` and `dep:name` enable whole features or dependencies
+                let (dep, sub_feature, weak) = match features::FeatureEntry::parse(entry) {
+                    features::FeatureEntry::DependencyFeature { dep, feature, weak } => (dep, feature, weak),
+                    features::FeatureEntry::Feature(_) | features::FeatureEntry::Dependency(_) => continue,
+                };
+                // The name in `[features]` may be a rename (`net = { package = "kernel_net" }`)
+                let pkg_name = crate_info.dependency_package(dep);
+                let dependency = if pkg_name == dep {
+                    format!("'{}'", dep)
+                } else {
+                    format!("'{}' (package '{}')", dep, pkg_name)
+                };
+                
+                // Key decision: Does the dependency support kbuild?
+                let kind = workspace.kind(pkg_name);
+                if kind == CrateKind::Kbuild {
+                    // A weak entry only adds the sub-feature to an already
+                    // enabled dependency, so there is nothing to replace it with
+                    let change = if weak {
+                        format!("Remove '{}' from {}", entry, feature_name)
                     } else {
-                        // ℹ️ Info: Third-party library - sub-feature allowed
-                        eprintln!(
-                            "ℹ️  '{}' is third-party, sub-feature allowed: {}\n",
-                            pkg_name, dep
-                        );
-                    }
+                        format!("Change to: {} = [\"{}\"]", feature_name, dep)
+                    };
+                    // ❌ Error: kbuild-enabled crate cannot specify sub-feature
+                    return Err(format!(
+                        "❌ Error in crate '{}':\n\
+                         \n\
+                         Feature '{}' specifies sub-feature: '{}'\n\
+                         \n\
+                         Dependency {} is kbuild-enabled:\n\
+                         - It reads CONFIG_* from .config directly\n\
+                         - Cannot be controlled by parent crate\n\
+                         \n\
+                         Solution:\n\
+                         1. {}\n\
+                         2. Enable {} in .config file\n\
+                         \n\
+                         Note: Third-party crates (e.g., log/std, tokio/rt) are allowed sub-features.\n",
+                        crate_info.name,
+                        feature_name,
+                        entry,
+                        dependency,
+                        change,
+                        sub_feature
+                    ));
+                } else if kind == CrateKind::Legacy {
+                    // ℹ️ Info: Legacy workspace crate - sub-feature allowed
+                    eprintln!(
+                        "ℹ️  {} is a legacy crate, sub-feature allowed: {}\n",
+                        dependency, entry
+                    );
+                } else {
+                    // ℹ️ Info: Third-party library - sub-feature allowed
+                    eprintln!(
+                        "ℹ️  {} is third-party, sub-feature allowed: {}\n",
+                        dependency, entry
+                    );
                 }
             }
         }
@@ -216,84 +544,187 @@ fn validate_features(workspace: &Workspace) -> Result<(), String> {
     Ok(())
 }
 
-/// Collect all CONFIG_* names from .config file
-fn collect_all_configs_from_file(config: &HashMap<String, String>) -> HashSet<String> {
-    let mut configs = HashSet::new();
+/// Qualify enabled features with the members declaring them (`pkg/FEATURE`)
+///
+/// A bare `--features NET` only reaches the packages cargo selected, and is
+/// an error if none of them declares it. cargo accepts `pkg/FEATURE` for
+/// every selected member, so each selected crate gets its own entries,
+/// followed by those of its `[package.metadata.kbuild.features]` table.
+/// Returns the features to pass and those of members outside the selection,
+/// which cannot be enabled from the command line.
+fn qualify_features(
+    config: &BTreeMap<String, String>,
+    workspace: &Workspace,
+    selected: &[&CrateInfo],
+) -> Result<(Vec<String>, Vec<String>), String> {
+    let features = generate_features(config);
+    let mut enabled = Vec::new();
+    let mut unselected = Vec::new();
     
-    for key in config.keys() {
-        configs.insert(key.clone());
+    for crate_info in &workspace.crates {
+        let is_selected = selected.iter().any(|c| c.id == crate_info.id);
+        let mut qualified: Vec<String> = features.iter()
+            .filter(|f| crate_info.features.contains_key(*f))
+            .map(|f| format!("{}/{}", crate_info.name, f))
+            .collect();
+        qualified.extend(features::mapped(crate_info, config)?);
+        
+        let target = if is_selected { &mut enabled } else { &mut unselected };
+        for feature in qualified {
+            if !target.contains(&feature) {
+                target.push(feature);
+            }
+        }
     }
     
-    configs
+    Ok((enabled, unselected))
 }
 
-/// Collect all CONFIG_* feature names from workspace crates (including root package)
-fn collect_all_configs(workspace: &Workspace) -> HashSet<String> {
-    let mut configs = HashSet::new();
+/// First line of every cargo config file generated by cargo-kbuild
+const GENERATED_CARGO_CONFIG_HEADER: &str = "# Auto-generated by cargo-kbuild";
+
+/// Generate target/kbuild/cargo-config.toml with the environment of kbuild runs
+///
+/// This file is owned by cargo-kbuild and loaded with `cargo --config <file>`,
+/// so the user's `.cargo/config.toml` (aliases, registries, target settings)
+/// never has to be touched. It carries no rustflags: check-cfg declarations
+/// only reach kbuild-enabled crates, through the rustc wrapper's per-crate
+/// flags or the kbuild_build helper. Returns the path of the generated file.
+fn generate_cargo_config(workspace_root: &Path, force: bool) -> Result<PathBuf, String> {
+    let target_dir = workspace_root.join("target/kbuild");
+    fs::create_dir_all(&target_dir)
+        .map_err(|e| format!("Failed to create target/kbuild: {}", e))?;
     
-    // Collect from all crates (not just kbuild-enabled) to include root package features
-    for crate_info in workspace.crates.iter() {
-        for feature_name in crate_info.features.keys() {
-            configs.insert(feature_name.clone());
-        }
+    let config_path = target_dir.join("cargo-config.toml");
+    
+    let mut content = format!("{}\n", GENERATED_CARGO_CONFIG_HEADER);
+    content.push_str("# Run 'cargo-kbuild build' to regenerate this file\n");
+    content.push_str("# DO NOT commit this file to git\n\n");
+    
+    // Location of generated files, where kbuild_build looks for check-cfg
+    // declarations and per-crate config modules. The path is
+    // absolute because `relative = true` would resolve against the directory
+    // containing this file's parent rather than the workspace root.
+    content.push_str("[env]\n");
+    content.push_str(&format!(
+        "KBUILD_OUT_DIR = {}\n",
+        toml::Value::String(target_dir.display().to_string())
+    ));
+    
+    if write_if_changed(&config_path, &content)? {
+        println!("✅ Generated {}", config_path.display());
+    } else {
+        println!("✔️  {} is up to date", display_path(workspace_root, &config_path));
     }
     
-    configs
+    install_cargo_config(workspace_root, &content, force)?;
+    
+    Ok(config_path)
+}
+
+/// Lines delimiting the part of .cargo/config.toml cargo-kbuild maintains
+const MANAGED_SECTION_BEGIN: &str = "# >>> cargo-kbuild managed section (rewritten by cargo-kbuild) >>>";
+const MANAGED_SECTION_END: &str = "# <<< cargo-kbuild managed section <<<";
+
+/// Byte range of the managed section in `content`, marker lines included
+fn managed_section(content: &str) -> Option<(usize, usize)> {
+    let start = content.find(MANAGED_SECTION_BEGIN)?;
+    let mut end = start + content[start..].find(MANAGED_SECTION_END)? + MANAGED_SECTION_END.len();
+    if content[end..].starts_with('\n') {
+        end += 1;
+    }
+    Some((start, end))
 }
 
-/// Generate .cargo/config.toml with check-cfg declarations
-fn generate_cargo_config(workspace_root: &Path, configs: &HashSet<String>) -> Result<(), String> {
+/// Mirror the generated settings into .cargo/config.toml for plain `cargo` runs
+///
+/// Only a managed section between two marker lines is ever rewritten, so
+/// settings added around it are kept. A file without the section is created
+/// with it, replaced if an older cargo-kbuild wrote it whole, and otherwise
+/// (hand-written) left untouched unless `force` is set, in which case the
+/// section is appended. The file is not written if the section would make
+/// it invalid, e.g. because the user's part defines the same table.
+fn install_cargo_config(workspace_root: &Path, content: &str, force: bool) -> Result<(), String> {
     let cargo_dir = workspace_root.join(".cargo");
-    fs::create_dir_all(&cargo_dir)
-        .map_err(|e| format!("Failed to create .cargo directory: {}", e))?;
-    
     let config_path = cargo_dir.join("config.toml");
+    let section = format!("{}\n{}{}\n", MANAGED_SECTION_BEGIN, content, MANAGED_SECTION_END);
     
-    let mut content = String::from("# Auto-generated by cargo-kbuild\n");
-    content.push_str("# This file declares all conditional compilation flags\n");
-    content.push_str("# Run 'cargo-kbuild build' to regenerate this file\n");
-    content.push_str("# DO NOT commit this file to git\n\n");
-    content.push_str("[build]\n");
-    content.push_str("rustflags = [\n");
-    
-    let mut sorted_configs: Vec<_> = configs.iter().collect();
-    sorted_configs.sort();
+    let updated = match fs::read_to_string(&config_path) {
+        Err(_) => section,
+        Ok(existing) => match managed_section(&existing) {
+            Some((start, end)) => format!("{}{}{}", &existing[..start], section, &existing[end..]),
+            // Written whole by cargo-kbuild before it used a managed section
+            None if existing.starts_with(GENERATED_CARGO_CONFIG_HEADER) => section,
+            None if !force => {
+                println!("ℹ️  .cargo/config.toml is hand-written, leaving it untouched");
+                println!("   Plain cargo runs can load kbuild settings with:");
+                println!("   cargo --config target/kbuild/cargo-config.toml <command>");
+                println!("   Re-run with --force to append a managed section (your settings are kept)");
+                return Ok(());
+            }
+            None => {
+                let mut updated = existing;
+                if !updated.is_empty() && !updated.ends_with('\n') {
+                    updated.push('\n');
+                }
+                updated.push('\n');
+                updated.push_str(&section);
+                updated
+            }
+        },
+    };
     
-    for config in sorted_configs {
-        content.push_str(&format!("    \"--check-cfg=cfg({})\",\n", config));
+    if let Err(e) = toml::from_str::<toml::Table>(&updated) {
+        println!("⚠️  Not updating .cargo/config.toml: the kbuild settings conflict with its content");
+        println!("   {}", e.message().trim().replace('\n', ": "));
+        println!("   Plain cargo runs can load kbuild settings with:");
+        println!("   cargo --config target/kbuild/cargo-config.toml <command>");
+        return Ok(());
     }
     
-    content.push_str("]\n");
-    
-    fs::write(&config_path, content)
-        .map_err(|e| format!("Failed to write .cargo/config.toml: {}", e))?;
+    fs::create_dir_all(&cargo_dir)
+        .map_err(|e| format!("Failed to create .cargo directory: {}", e))?;
     
-    println!("✅ Generated .cargo/config.toml with {} config declarations", configs.len());
+    if write_if_changed(&config_path, &updated)? {
+        println!("✅ Generated .cargo/config.toml");
+    } else {
+        println!("✔️  .cargo/config.toml is up to date");
+    }
     Ok(())
 }
 
-/// Parse .config file
-fn parse_config(config_path: &Path) -> Result<HashMap<String, String>, String> {
+/// Symbol name -> location (`file:line`) where it was set
+type ConfigSources = BTreeMap<String, String>;
+
+/// Parse .config file, also recording where each symbol was set
+///
+/// Sources are reported as `<display_name>:<line>`; a later assignment of the
+/// same symbol overrides an earlier one, as with Kconfig.
+fn parse_config_with_sources(
+    config_path: &Path,
+    display_name: &str,
+) -> Result<(BTreeMap<String, String>, ConfigSources), String> {
     let content = fs::read_to_string(config_path)
         .map_err(|e| format!("Failed to read .config: {}", e))?;
     
-    let mut config = HashMap::new();
-    for line in content.lines() {
-        let line = line.trim();
-        if line.is_empty() || line.starts_with('#') {
-            continue;
-        }
-        
-        if let Some((key, value)) = line.split_once('=') {
-            config.insert(key.trim().to_string(), value.trim().to_string());
+    let mut config = BTreeMap::new();
+    let mut sources = BTreeMap::new();
+    for assignment in dotconfig::parse(&content) {
+        // `# X is not set` lines leave the symbol out, like `X=n` does
+        if let Some(value) = assignment.value {
+            sources.insert(assignment.name.clone(), format!("{}:{}", display_name, assignment.line));
+            config.insert(assignment.name, value);
         }
     }
     
-    Ok(config)
+    Ok((config, sources))
 }
 
 /// Generate features based on .config
-fn generate_features(config: &HashMap<String, String>) -> Vec<String> {
+///
+/// Symbols set to `m` enable their feature too: a module still needs its
+/// optional dependencies, only its cfg (`NAME_MODULE`) differs.
+fn generate_features(config: &BTreeMap<String, String>) -> Vec<String> {
     let mut features = Vec::new();
     
     for (key, value) in config {
@@ -305,8 +736,140 @@ fn generate_features(config: &HashMap<String, String>) -> Vec<String> {
     features
 }
 
+/// Compute a stable fingerprint of the resolved configuration
+///
+/// Uses 64-bit FNV-1a over the sorted `KEY=VALUE` lines, so the result only
+/// depends on the configuration itself (not on file layout, comments or the
+/// Rust toolchain in use).
+fn config_fingerprint(config: &BTreeMap<String, String>) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for (key, value) in config {
+        let line = format!("{}={}\n", key, value);
+        for byte in line.bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    format!("{:016x}", hash)
+}
+
+/// Determine the build timestamp (seconds since the Unix epoch)
+///
+/// Only `SOURCE_DATE_EPOCH` is used: any clock or file time would make the
+/// generated files differ for the same configuration, so there is no
+/// timestamp without it.
+fn build_timestamp() -> Result<Option<u64>, String> {
+    match env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) => epoch.trim().parse::<u64>()
+            .map(Some)
+            .map_err(|e| format!("Invalid SOURCE_DATE_EPOCH '{}': {}", epoch, e)),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Generate the build-info constants appended to config.rs
+fn generate_build_info(
+    workspace_root: &Path,
+    config_path: &Path,
+    config: &BTreeMap<String, String>,
+) -> Result<String, String> {
+    let version = env!("CARGO_PKG_VERSION");
+    let hash = config_fingerprint(config);
+    let config_file = display_path(workspace_root, config_path);
+    let timestamp = build_timestamp()?;
+    let mut info = format!("cargo-kbuild {} config={} ({})", version, hash, config_file);
+    if let Some(timestamp) = timestamp {
+        info.push_str(&format!(" timestamp={}", timestamp));
+    }
+
+    let mut content = String::new();
+    content.push_str("// Build information\n\n");
+    content.push_str("#[allow(dead_code)]\n");
+    content.push_str(&format!("pub const KBUILD_VERSION: &str = {:?};\n\n", version));
+    content.push_str("#[allow(dead_code)]\n");
+    content.push_str(&format!("pub const KBUILD_CONFIG_HASH: &str = {:?};\n\n", hash));
+    content.push_str("#[allow(dead_code)]\n");
+    content.push_str(&format!("pub const KBUILD_CONFIG_FILES: &[&str] = &[{:?}];\n\n", config_file));
+    content.push_str("#[allow(dead_code)]\n");
+    content.push_str(&format!("pub const KBUILD_TIMESTAMP: Option<u64> = {:?};\n\n", timestamp));
+    content.push_str("#[allow(dead_code)]\n");
+    content.push_str(&format!("pub const KBUILD_BUILD_INFO: &str = {:?};\n", info));
+
+    Ok(content)
+}
+
+/// Write a file only if its content differs from what is on disk
+///
+/// Leaving unchanged files untouched keeps their mtime, so rustc/cargo
+/// dependency tracking does not consider them modified.
+///
+/// Returns `true` if the file was written.
+fn write_if_changed(path: &Path, content: &str) -> Result<bool, String> {
+    if fs::read_to_string(path).map(|existing| existing == content).unwrap_or(false) {
+        return Ok(false);
+    }
+    
+    fs::write(path, content)
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    Ok(true)
+}
+
+/// Generate per-crate config modules for crates that declare the symbols they read
+///
+/// A crate listing `symbols = [...]` under `[package.metadata.kbuild]` gets
+/// `target/kbuild/crates/<name>.rs` containing only those constants. The file
+/// is rewritten only when its content changes, so rustc's dependency tracking
+/// rebuilds the crate only when one of its own symbols changes.
+fn generate_crate_configs(
+    workspace_root: &Path,
+    workspace: &Workspace,
+    config: &BTreeMap<String, String>,
+    symbol_table: &symbols::SymbolTable,
+) -> Result<(), String> {
+    let crates_dir = workspace_root.join("target/kbuild/crates");
+    
+    for crate_info in workspace.crates.iter().chain(workspace.external_kbuild_crates()) {
+        let symbols = match &crate_info.symbols {
+            Some(symbols) => symbols,
+            None => continue,
+        };
+        
+        fs::create_dir_all(&crates_dir)
+            .map_err(|e| format!("Failed to create target/kbuild/crates: {}", e))?;
+        
+        let header = format!(
+            "// Auto-generated by cargo-kbuild for crate '{}'\n\
+             // Only contains the symbols listed in [package.metadata.kbuild] symbols\n\
+             // DO NOT EDIT MANUALLY\n\n",
+            crate_info.name
+        );
+        let (content, missing) = kbuild_build::render::crate_module(&header, symbols, config, symbol_table)?;
+        for symbol in missing {
+            eprintln!(
+                "⚠️  Crate '{}' declares symbol '{}' which is not set in .config",
+                crate_info.name, symbol
+            );
+        }
+        
+        let crate_config_path = crates_dir.join(format!("{}.rs", crate_info.name));
+        if write_if_changed(&crate_config_path, &content)? {
+            println!("📝 Generated config module for '{}' at: {}", crate_info.name, crate_config_path.display());
+        } else {
+            println!("✔️  Config module for '{}' is up to date", crate_info.name);
+        }
+    }
+    
+    Ok(())
+}
+
 /// Generate config.rs file with constants
-fn generate_config_rs(workspace_root: &Path, config: &HashMap<String, String>) -> Result<(), String> {
+fn generate_config_rs(
+    workspace_root: &Path,
+    config_path: &Path,
+    config: &BTreeMap<String, String>,
+    symbol_table: &symbols::SymbolTable,
+) -> Result<(), String> {
     // Create target/kbuild directory
     let target_dir = workspace_root.join("target/kbuild");
     fs::create_dir_all(&target_dir)
@@ -319,85 +882,243 @@ fn generate_config_rs(workspace_root: &Path, config: &HashMap<String, String>) -
     content.push_str("// Auto-generated by cargo-kbuild from .config\n");
     content.push_str("// DO NOT EDIT MANUALLY\n\n");
     
+    content.push_str(TRISTATE_DEFINITION);
+    
     // Process each config value
     for (key, value) in config {
-        // Skip boolean configs (y/n) as they're handled via --cfg
-        if value == "y" || value == "n" || value == "m" {
-            continue;
-        }
-        
-        // Try to parse as integer
-        if let Ok(int_val) = value.parse::<i32>() {
-            content.push_str(&format!("#[allow(dead_code)]\n"));
-            content.push_str(&format!("pub const {}: i32 = {};\n\n", key, int_val));
-        }
-        // Check if it's a string (starts and ends with quotes)
-        else if value.starts_with('"') && value.ends_with('"') {
-            let str_val = &value[1..value.len()-1]; // Remove quotes
-            content.push_str(&format!("#[allow(dead_code)]\n"));
-            content.push_str(&format!("pub const {}: &str = \"{}\";\n\n", key, str_val));
-        }
-        // Otherwise treat as usize
-        else if let Ok(uint_val) = value.parse::<usize>() {
-            content.push_str(&format!("#[allow(dead_code)]\n"));
-            content.push_str(&format!("pub const {}: usize = {};\n\n", key, uint_val));
+        let kind = symbol_table.symbol_type(key, config)?;
+        if let Some(constant) = render_constant(key, value, kind) {
+            content.push_str(&constant);
         }
     }
     
-    // Write the file
-    fs::write(&config_rs_path, content)
-        .map_err(|e| format!("Failed to write config.rs: {}", e))?;
+    content.push_str(&generate_build_info(workspace_root, config_path, config)?);
     
-    println!("📝 Generated config.rs at: {}", config_rs_path.display());
+    // Write the file only if it changed, so kbuild_config's rerun-if-changed
+    // does not fire on an unchanged configuration
+    if write_if_changed(&config_rs_path, &content)? {
+        println!("📝 Generated config.rs at: {}", config_rs_path.display());
+    } else {
+        println!("✔️  config.rs is up to date");
+    }
     
     Ok(())
 }
 
-/// Apply kbuild configuration and run cargo command
-///
-/// # Arguments
-/// * `workspace_root` - Root directory of the workspace
-/// * `config_path` - Path to the .config file
-/// * `cargo_cmd` - The cargo command to run (e.g., "build", "test", "check")
-/// * `extra_args` - Additional arguments passed to cargo
-///
-/// # Returns
-/// * `Ok(())` on success
-/// * `Err(String)` with error message on failure
-fn apply_kbuild_config(
+/// Report how many crates a symbol change rebuilds compared to RUSTFLAGS
+fn print_rebuild_savings(workspace_root: &Path, kbuild_crates: usize, changed: &[String]) {
+    if changed.is_empty() {
+        println!("♻️  No kbuild crate uses a changed symbol");
+        return;
+    }
+    
+    let spared = kbuild_crates - changed.len();
+    match fixdep::locked_package_count(workspace_root) {
+        Some(total) => println!(
+            "♻️  Config change affects {} of {} kbuild crate(s) (plus their dependents); {} kbuild crate(s) and {} other package(s) are spared (RUSTFLAGS would rebuild all {})",
+            changed.len(),
+            kbuild_crates,
+            spared,
+            total.saturating_sub(kbuild_crates),
+            total
+        ),
+        None => println!(
+            "♻️  Config change affects {} of {} kbuild crate(s) (plus their dependents); {} are spared",
+            changed.len(),
+            kbuild_crates,
+            spared
+        ),
+    }
+}
+
+/// Everything generated from the .config before cargo runs
+struct KbuildSetup {
+    workspace: Workspace,
+    /// Absolute path of the .config
+    config_path: PathBuf,
+    config: BTreeMap<String, String>,
+    symbol_table: symbols::SymbolTable,
+    check_cfgs: Vec<String>,
+    /// target/kbuild/cargo-config.toml, loaded into every cargo run
+    cargo_config: PathBuf,
+    /// Features enabled in .config and declared by a workspace crate
+    features: Vec<String>,
+    /// Environment for cargo and build scripts (see `kbuild_env`)
+    env: Vec<(&'static str, PathBuf)>,
+}
+
+/// Environment cargo-kbuild passes to cargo
+fn kbuild_env(
     workspace_root: &Path,
-    config_path: &Path,
-    cargo_cmd: &str,
-    extra_args: &[String],
-) -> Result<(), String> {
-    println!("🔨 Running cargo {} with kbuild configuration...\n", cargo_cmd);
+    absolute_config: &Path,
+    autoconf_path: Option<PathBuf>,
+) -> Vec<(&'static str, PathBuf)> {
+    // Location of generated files (per-crate config modules)
+    let mut env = vec![("KBUILD_OUT_DIR", workspace_root.join("target/kbuild"))];
+    
+    // The .config in use, so build scripts calling kbuild_build::configure()
+    // apply the same configuration (including one passed with --kconfig)
+    env.push(("KBUILD_CONFIG", absolute_config.to_path_buf()));
+    
+    // Let build scripts (e.g. using the `cc` crate) locate autoconf.h
+    if let Some(autoconf_path) = autoconf_path {
+        env.push(("KBUILD_AUTOCONF_H", autoconf_path));
+    }
+    
+    env
+}
+
+/// Where cargo-kbuild runs and which .config it applies
+struct Invocation {
+    /// Directory cargo runs in: the current directory, or `-C <dir>`
+    cwd: PathBuf,
+    workspace_root: PathBuf,
+    /// `--manifest-path` given before the command, forwarded to cargo
+    manifest_path: Option<PathBuf>,
+    /// The .config, resolved against the workspace root
+    kconfig: PathBuf,
+    /// Add the managed section to a hand-written `.cargo/config.toml`
+    force: bool,
+    /// Fail on workspace members whose Cargo.toml cannot be read
+    strict: bool,
+}
+
+impl Invocation {
+    /// Locate the workspace from `-C`, `--manifest-path` (before the command
+    /// or among the cargo arguments) or the current directory
+    fn new(
+        directory: Option<PathBuf>,
+        manifest_path: Option<PathBuf>,
+        kconfig: Option<PathBuf>,
+        force: bool,
+        strict: bool,
+        cargo_args: &[String],
+    ) -> Result<Invocation, String> {
+        let current_dir = env::current_dir()
+            .map_err(|e| format!("Failed to get current directory: {}", e))?;
+        let cwd = match directory {
+            Some(dir) => locate::absolute(&current_dir, &dir),
+            None => current_dir,
+        };
+        if !cwd.is_dir() {
+            return Err(format!("-C {}: not a directory", cwd.display()));
+        }
+        
+        let manifest_path = manifest_path.map(|path| locate::absolute(&cwd, &path));
+        let cargo_manifest = locate::CargoSelection::from_args(cargo_args).manifest_path
+            .map(|path| locate::absolute(&cwd, &path));
+        let workspace_root = locate::workspace_root(&cwd, manifest_path.as_deref().or(cargo_manifest.as_deref()))?;
+        
+        let kconfig = locate::absolute(&workspace_root, &kconfig.unwrap_or_else(|| PathBuf::from(".config")));
+        
+        Ok(Invocation { cwd, workspace_root, manifest_path, kconfig, force, strict })
+    }
+    
+    /// Workspace members the cargo command builds, following cargo's rules:
+    /// `--workspace`, `-p`, the package of the manifest or current directory,
+    /// otherwise the default members
+    fn selected_crates<'a>(&self, workspace: &'a Workspace, cargo_args: &[String]) -> Vec<&'a CrateInfo> {
+        let selection = locate::CargoSelection::from_args(cargo_args);
+        if selection.workspace {
+            return workspace.crates.iter().collect();
+        }
+        if !selection.packages.is_empty() {
+            // `-p name@version` selects by name as well
+            let names: BTreeSet<&str> = selection.packages.iter()
+                .map(|p| p.split('@').next().unwrap_or(p))
+                .collect();
+            return workspace.crates.iter().filter(|c| names.contains(c.name.as_str())).collect();
+        }
+        
+        let manifest = self.manifest_path.clone()
+            .or_else(|| selection.manifest_path.map(|path| locate::absolute(&self.cwd, &path)))
+            .or_else(|| locate::nearest_manifest(&self.cwd));
+        let package_dir = manifest.as_deref().and_then(Path::parent).and_then(|dir| fs::canonicalize(dir).ok());
+        let root = fs::canonicalize(&self.workspace_root).ok();
+        if let Some(package_dir) = package_dir.filter(|dir| Some(dir) != root.as_ref()) {
+            if let Some(crate_info) = workspace.crates.iter()
+                .find(|c| fs::canonicalize(&c.path).ok().as_ref() == Some(&package_dir))
+            {
+                return vec![crate_info];
+            }
+        }
+        
+        workspace.crates.iter().filter(|c| workspace.default_members.contains(&c.name)).collect()
+    }
+}
+
+/// Validate the workspace and generate the config files for the invocation
+///
+/// Writes config.rs, per-crate config modules, the selected exports and the
+/// check-cfg cargo config; everything except running cargo itself.
+/// `cargo_args` select the packages whose features are enabled.
+fn prepare_kbuild(invocation: &Invocation, cargo_args: &[String]) -> Result<KbuildSetup, String> {
+    let workspace_root = invocation.workspace_root.as_path();
+    let config_path = invocation.kconfig.as_path();
     
     // Parse workspace
-    let workspace = Workspace::new(workspace_root.to_path_buf())?;
+    let workspace = Workspace::new(workspace_root.to_path_buf(), invocation.strict)?;
+    println!("📦 Workspace: {}\n", workspace.describe());
+    
+    let external_kbuild: Vec<&CrateInfo> = workspace.external_kbuild_crates().collect();
+    if !external_kbuild.is_empty() {
+        println!("🔗 kbuild crates outside the workspace:");
+        for crate_info in &external_kbuild {
+            println!("  - {} ({})", crate_info.name, crate_info.path.display());
+        }
+        println!();
+    }
     
     // Validate features
     validate_features(&workspace)?;
     
     // Parse .config to get all CONFIG_* options
-    let config = parse_config(config_path)?;
+    let (config, sources) = parse_config_with_sources(
+        config_path,
+        &display_path(workspace_root, config_path),
+    )?;
+    
+    // Collect every known symbol (Kconfig, defconfigs, .config, metadata)
+    let symbol_table = symbols::SymbolTable::collect(
+        workspace_root,
+        workspace.metadata.sources.kconfig_path(workspace_root)?.as_deref(),
+        &workspace.metadata.sources.defconfig_paths(workspace_root)?,
+        Some(config_path),
+        &workspace.metadata.sources.symbols,
+    )?;
+    
+    // Enforce `depends on`, including tristate limits (m cannot satisfy y)
+    symbol_table.check_dependencies(&config)?;
+    features::check(&workspace, &symbol_table, &config)?;
     
     // Generate config.rs file with constants
-    generate_config_rs(workspace_root, &config)?;
-    println!();
+    generate_config_rs(workspace_root, config_path, &config, &symbol_table)?;
+    
+    // Generate filtered config modules for crates that declare their symbols
+    generate_crate_configs(workspace_root, &workspace, &config, &symbol_table)?;
     
-    // Collect all CONFIG_* names from .config file and generate .cargo/config.toml
-    let all_configs = collect_all_configs_from_file(&config);
-    generate_cargo_config(workspace_root, &all_configs)?;
+    // Generate the selected exports (autoconf.h, config.json, auto.conf, config.sh)
+    let outputs = workspace.metadata.outputs()?;
+    let autoconf_path = exports::generate_exports(
+        workspace_root,
+        &outputs,
+        &config,
+        &sources,
+        &config_fingerprint(&config),
+    )?;
     println!();
     
-    // Generate features - only include features that are declared in Cargo.toml
-    let features = generate_features(&config);
-    let declared_features = collect_all_configs(&workspace);
+    // Declare every known symbol for check-cfg, not just those set in .config
+    println!("🔎 check-cfg covers {} symbols from: {}", symbol_table.len(), symbol_table.describe_sources());
+    let check_cfgs = symbol_table.check_cfgs(&config)?;
+    let cargo_config = generate_cargo_config(workspace_root, invocation.force)?;
+    println!();
     
-    // Filter to only features that are actually declared in Cargo.toml
-    let filtered_features: Vec<String> = features.into_iter()
-        .filter(|f| declared_features.contains(f))
-        .collect();
+    // Generate features as `pkg/FEATURE` for the members cargo builds that
+    // declare them or map them from symbols (cargo rejects features of
+    // other packages)
+    let selected = invocation.selected_crates(&workspace, cargo_args);
+    let (filtered_features, unselected_features) = qualify_features(&config, &workspace, &selected)?;
     
     println!("📋 Enabled features from .config:");
     for feature in &filtered_features {
@@ -406,50 +1127,177 @@ fn apply_kbuild_config(
     if filtered_features.is_empty() {
         println!("  (none - all CONFIG_* used via cfg flags)");
     }
+    if !unselected_features.is_empty() {
+        println!(
+            "ℹ️  Not enabled, the package is not selected (use -p or --workspace): {}",
+            unselected_features.join(" ")
+        );
+    }
     println!();
     
-    // Build cargo command
-    let mut cargo_args = vec![cargo_cmd.to_string()];
+    let absolute_config = fs::canonicalize(config_path)
+        .map_err(|e| format!("Failed to resolve {}: {}", config_path.display(), e))?;
+    let env = kbuild_env(workspace_root, &absolute_config, autoconf_path);
     
-    if !filtered_features.is_empty() {
-        cargo_args.push("--features".to_string());
-        cargo_args.push(filtered_features.join(","));
-    }
+    Ok(KbuildSetup {
+        workspace,
+        config_path: absolute_config,
+        config,
+        symbol_table,
+        check_cfgs,
+        cargo_config,
+        features: filtered_features,
+        env,
+    })
+}
+
+/// rust-analyzer settings for a prepared configuration
+///
+/// rust-analyzer works on the whole workspace, so the features of every
+/// member are enabled, whichever packages the current command selected.
+fn ide_settings(setup: &KbuildSetup) -> Result<serde_json::Map<String, serde_json::Value>, String> {
+    let members: Vec<&CrateInfo> = setup.workspace.crates.iter().collect();
+    let (features, _) = qualify_features(&setup.config, &setup.workspace, &members)?;
+    ide::kbuild_settings(&setup.config_path, &setup.config, &features, &setup.env)
+}
+
+/// Apply kbuild configuration and run cargo command
+///
+/// # Arguments
+/// * `invocation` - Workspace, directory and .config to use
+/// * `cargo_cmd` - The cargo command to run (e.g., "build", "test", "check")
+/// * `extra_args` - Additional arguments passed to cargo
+///
+/// # Returns
+/// * `Ok(())` on success
+/// * `Err(String)` with error message on failure
+fn apply_kbuild_config(
+    invocation: &Invocation,
+    cargo_cmd: &str,
+    extra_args: &[String],
+) -> Result<(), String> {
+    println!("🔨 Running cargo {} with kbuild configuration...\n", cargo_cmd);
     
-    // Add extra arguments
-    cargo_args.extend_from_slice(extra_args);
+    let workspace_root = invocation.workspace_root.as_path();
+    let setup = prepare_kbuild(invocation, extra_args)?;
+    
+    // Keep the editor in sync once `cargo kbuild ide-setup` has been run
+    if ide::refresh_settings(workspace_root, ide_settings(&setup)?)? {
+        println!("🧭 Refreshed rust-analyzer settings in .vscode/settings.json\n");
+    }
     
-    println!("🚀 Running: cargo {}\n", cargo_args.join(" "));
+    let KbuildSetup { workspace, config, symbol_table, check_cfgs, cargo_config, features: filtered_features, env, .. } = setup;
     
-    // Set RUSTFLAGS to enable config values as cfg flags and declare them for check-cfg
-    let mut rustflags = String::new();
+    // Collect the rustc flags that enable config values as cfgs and declare
+    // them for check-cfg (one rustc argument per element)
+    let mut kbuild_flags = Vec::new();
     
     // Add check-cfg declarations for all config options from .config
-    for config_name in all_configs.iter() {
-        if !rustflags.is_empty() {
-            rustflags.push(' ');
-        }
-        rustflags.push_str(&format!("--check-cfg=cfg({})", config_name));
+    for check_cfg in &check_cfgs {
+        kbuild_flags.push(format!("--check-cfg={}", check_cfg));
     }
     
-    // Add --cfg flags for ALL enabled configs from .config (not just features)
+    // Add --cfg flags for ALL enabled configs from .config (not just features):
+    // `SMP` for bools, `LOG_LEVEL="3"` for ints and strings
     for (key, value) in &config {
-        if value == "y" || value == "m" {
-            if !rustflags.is_empty() {
-                rustflags.push(' ');
-            }
-            rustflags.push_str(&format!("--cfg {}", key));
+        if let Some(cfg) = symbols::cfg_flag(key, value) {
+            kbuild_flags.push("--cfg".to_string());
+            kbuild_flags.push(cfg);
         }
     }
     
     let mut cmd = process::Command::new("cargo");
-    cmd.args(&cargo_args);
-    cmd.current_dir(workspace_root);
+    let mut cargo_args = Vec::new();
+    
+    match workspace.metadata.cfg_mode {
+        CfgMode::Wrapper => {
+            // Only crates that opted in via metadata receive the cfgs;
+            // third-party and legacy crates are compiled untouched
+            let kbuild_crates: Vec<&CrateInfo> = workspace.kbuild_crates().collect();
+            
+            // fixdep: each crate only gets the cfgs of the symbols it uses,
+            // so a symbol change rebuilds only the crates referencing it
+            let check_cfgs_by_symbol = symbol_table.check_cfgs_by_symbol(&config)?;
+            let known: BTreeSet<String> = check_cfgs_by_symbol.keys().cloned().collect();
+            let mut usage = BTreeMap::new();
+            let mut crate_flags = Vec::new();
+            for crate_info in &kbuild_crates {
+                let mut used = fixdep::used_symbols(&crate_info.path, &known)?;
+                used.extend(crate_info.symbols.iter().flatten().cloned());
+                
+                let mut flags = Vec::new();
+                for symbol in &used {
+                    for check_cfg in check_cfgs_by_symbol.get(symbol).into_iter().flatten() {
+                        flags.push(format!("--check-cfg={}", check_cfg));
+                    }
+                    if let Some(cfg) = config.get(symbol).and_then(|value| symbols::cfg_flag(symbol, value)) {
+                        flags.push("--cfg".to_string());
+                        flags.push(cfg);
+                    }
+                }
+                
+                crate_flags.push(wrapper::CrateFlags {
+                    name: crate_info.name.clone(),
+                    dir: crate_info.path.clone(),
+                    flags,
+                });
+                usage.insert(crate_info.name.clone(), used);
+            }
+            
+            let first_run = !workspace_root.join("target/kbuild/cfgs").is_dir();
+            let changed = wrapper::configure(workspace_root, &mut cmd, &kbuild_flags, &crate_flags)?;
+            write_if_changed(&workspace_root.join("target/kbuild/symbol-usage.txt"), &fixdep::render_usage(&usage))?;
+            
+            println!("🎯 kbuild cfgs applied via rustc wrapper to {} crate(s):", kbuild_crates.len());
+            for (crate_name, used) in &usage {
+                let used: Vec<&str> = used.iter().map(|s| s.as_str()).collect();
+                let marker = if changed.contains(crate_name) && !first_run { " (symbols changed)" } else { "" };
+                let used = if used.is_empty() { "(no symbols)".to_string() } else { used.join(" ") };
+                println!("  - {}: {}{}", crate_name, used, marker);
+            }
+            
+            if !first_run {
+                print_rebuild_savings(workspace_root, kbuild_crates.len(), &changed);
+            }
+        }
+        CfgMode::Rustflags => {
+            // Merge with the user's RUSTFLAGS / config-file rustflags instead
+            // of overriding them
+            let delivery = rustflags::plan(&invocation.cwd, &kbuild_flags)?;
+            cargo_args = delivery.apply(&mut cmd);
+            println!("🏁 Rustflags {}", delivery.describe());
+        }
+    }
     
-    if !rustflags.is_empty() {
-        cmd.env("RUSTFLAGS", rustflags);
+    cargo_args.push("--config".to_string());
+    cargo_args.push(cargo_config.display().to_string());
+    cargo_args.push(cargo_cmd.to_string());
+    
+    if let Some(manifest_path) = &invocation.manifest_path {
+        cargo_args.push("--manifest-path".to_string());
+        cargo_args.push(manifest_path.display().to_string());
+    }
+    
+    if !filtered_features.is_empty() {
+        cargo_args.push("--features".to_string());
+        cargo_args.push(filtered_features.join(","));
+    }
+    
+    // Add extra arguments
+    cargo_args.extend_from_slice(extra_args);
+    
+    // cargo runs where cargo-kbuild was invoked, so it selects the same
+    // package a plain cargo command would
+    if invocation.cwd == workspace_root {
+        println!("🚀 Running: cargo {}\n", cargo_args.join(" "));
+    } else {
+        println!("🚀 Running in {}: cargo {}\n", display_path(workspace_root, &invocation.cwd), cargo_args.join(" "));
     }
     
+    cmd.args(&cargo_args);
+    cmd.current_dir(&invocation.cwd);
+    cmd.envs(env);
+    
     let status = cmd.status()
         .map_err(|e| format!("Failed to run cargo: {}", e))?;
     
@@ -469,9 +1317,25 @@ enum Cargo {
 
 #[derive(Args, Debug)]
 struct KbuildCommand {
-    /// Path to .config file
-    #[arg(long, default_value = ".config")]
-    kconfig: PathBuf,
+    /// Path to .config file, relative to the workspace root [default: .config]
+    #[arg(long)]
+    kconfig: Option<PathBuf>,
+
+    /// Add a managed section to a hand-written .cargo/config.toml (its settings are kept)
+    #[arg(long)]
+    force: bool,
+
+    /// Fail if a workspace member's Cargo.toml cannot be read
+    #[arg(long)]
+    strict: bool,
+
+    /// Run as if started in <DIR>
+    #[arg(short = 'C', value_name = "DIR")]
+    directory: Option<PathBuf>,
+
+    /// Cargo.toml of the package or workspace to use (forwarded to cargo)
+    #[arg(long, value_name = "PATH")]
+    manifest_path: Option<PathBuf>,
 
     #[command(subcommand)]
     command: Option<KbuildSubcommand>,
@@ -504,26 +1368,92 @@ enum KbuildSubcommand {
         #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
         args: Vec<String>,
     },
+    /// Write rust-analyzer settings for the current .config
+    IdeSetup,
+    /// Write rust-project.json for builds that invoke rustc directly
+    RustProject,
     /// Any other cargo command
     #[command(external_subcommand)]
     External(Vec<String>),
 }
 
-fn run_cargo_with_kbuild(
-    workspace_root: &Path,
-    kconfig_path: &Path,
-    cargo_cmd: &str,
-    extra_args: &[String],
-) {
-    if let Err(e) = apply_kbuild_config(workspace_root, kconfig_path, cargo_cmd, extra_args) {
+/// Generate the config files and write rust-analyzer settings for them
+fn setup_ide(invocation: &Invocation) -> Result<(), String> {
+    let workspace_root = invocation.workspace_root.as_path();
+    println!("🧭 Setting up rust-analyzer for {}...\n", display_path(workspace_root, &invocation.kconfig));
+    
+    let setup = prepare_kbuild(invocation, &["--workspace".to_string()])?;
+    if ide::write_settings(workspace_root, ide_settings(&setup)?)? {
+        println!("✅ Wrote rust-analyzer settings to .vscode/settings.json");
+    } else {
+        println!("✔️  .vscode/settings.json is up to date");
+    }
+    println!("   cfgs, features and environment follow .config on every cargo-kbuild run");
+    Ok(())
+}
+
+/// Generate the config files and describe the crate graph in rust-project.json
+fn write_rust_project(invocation: &Invocation) -> Result<(), String> {
+    let workspace_root = invocation.workspace_root.as_path();
+    println!("🗺️  Describing the workspace for {}...\n", display_path(workspace_root, &invocation.kconfig));
+    
+    let setup = prepare_kbuild(invocation, &["--workspace".to_string()])?;
+    let graph = metadata::load(workspace_root, &setup.features)?;
+    let summary = rust_project::generate(workspace_root, &setup.workspace, &graph, &setup.config)?;
+    
+    let path = display_path(workspace_root, &summary.path);
+    if summary.changed {
+        println!("✅ Generated {} with {} crate(s)", path, summary.crates);
+    } else {
+        println!("✔️  {} is up to date", path);
+    }
+    if !summary.external.is_empty() {
+        println!("ℹ️  Dependencies outside the workspace are not described:");
+        for dependency in &summary.external {
+            println!("  - {}", dependency);
+        }
+    }
+    Ok(())
+}
+
+fn run_rust_project(invocation: &Invocation) {
+    if let Err(e) = write_rust_project(invocation) {
         eprintln!("❌ Error: {}", e);
         process::exit(1);
     }
 }
 
+fn run_ide_setup(invocation: &Invocation) {
+    if let Err(e) = setup_ide(invocation) {
+        eprintln!("❌ Error: {}", e);
+        process::exit(1);
+    }
+}
 
+fn run_cargo_with_kbuild(invocation: &Invocation, cargo_cmd: &str, extra_args: &[String]) {
+    if let Err(e) = apply_kbuild_config(invocation, cargo_cmd, extra_args) {
+        eprintln!("❌ Error: {}", e);
+        process::exit(1);
+    }
+}
 
-
+/// Locate the workspace, exiting with an error message if that fails
+fn locate_or_exit(
+    directory: Option<PathBuf>,
+    manifest_path: Option<PathBuf>,
+    kconfig: Option<PathBuf>,
+    force: bool,
+    strict: bool,
+    cargo_args: &[String],
+) -> Invocation {
+    match Invocation::new(directory, manifest_path, kconfig, force, strict, cargo_args) {
+        Ok(invocation) => invocation,
+        Err(e) => {
+            eprintln!("❌ Error: {}", e);
+            process::exit(1);
+        }
+    }
+}
 
 /// Print help message
 fn print_help() {
@@ -534,7 +1464,11 @@ fn print_help() {
     println!("    cargo kbuild [OPTIONS] <COMMAND>");
     println!();
     println!("OPTIONS:");
-    println!("    --kconfig <FILE>    Path to .config file [default: .config]");
+    println!("    --kconfig <FILE>    Path to .config file, relative to the workspace root [default: .config]");
+    println!("    --force             Add a managed section to a hand-written .cargo/config.toml");
+    println!("    --strict            Fail if a workspace member's Cargo.toml cannot be read");
+    println!("    -C <DIR>            Run as if started in <DIR>");
+    println!("    --manifest-path <PATH>  Cargo.toml of the package or workspace to use");
     println!();
     println!("COMMANDS:");
     println!("    build               Build the project");
@@ -542,6 +1476,8 @@ fn print_help() {
     println!("    run                 Run a binary");
     println!("    check               Check the project");
     println!("    clippy              Run clippy");
+    println!("    ide-setup           Write rust-analyzer settings for .config");
+    println!("    rust-project        Write rust-project.json for non-cargo builds");
     println!("    <any-cargo-cmd>     Any other cargo command");
     println!();
     println!("EXAMPLES:");
@@ -551,6 +1487,9 @@ fn print_help() {
     println!("    cargo kbuild check --all-targets");
     println!("    cargo kbuild clippy -- -D warnings");
     println!("    cargo kbuild build --kconfig custom.config");
+    println!("    cargo kbuild build -p kernel_net");
+    println!("    cargo kbuild -C crates/kernel_net check");
+    println!("    cargo kbuild ide-setup");
 }
 
 /// Print version information
@@ -577,7 +1516,64 @@ fn extract_kconfig_arg(args: &[String]) -> (Option<PathBuf>, Vec<String>) {
     (kconfig_path, remaining)
 }
 
+/// cargo-kbuild options given before the cargo command
+#[derive(Debug, Default)]
+struct LeadingOptions {
+    force: bool,
+    strict: bool,
+    /// `-C <dir>`
+    directory: Option<PathBuf>,
+    manifest_path: Option<PathBuf>,
+}
+
+/// Extract --force, --strict, -C and --manifest-path (before the cargo command) from arguments
+///
+/// Only options before the command are considered, since `--force` after it
+/// belongs to cargo (e.g. `cargo-kbuild install --force`).
+fn extract_leading_options(args: &[String]) -> (LeadingOptions, Vec<String>) {
+    let mut options = LeadingOptions::default();
+    let mut remaining = Vec::new();
+    let mut before_command = true;
+    let mut iter = args.iter();
+    
+    while let Some(arg) = iter.next() {
+        if before_command && arg == "--force" {
+            options.force = true;
+            continue;
+        }
+        if before_command && arg == "--strict" {
+            options.strict = true;
+            continue;
+        }
+        if before_command && arg == "-C" {
+            options.directory = iter.next().map(PathBuf::from);
+            continue;
+        }
+        if before_command && arg == "--manifest-path" {
+            options.manifest_path = iter.next().map(PathBuf::from);
+            continue;
+        }
+        if before_command && arg == "--kconfig" {
+            // Keep --kconfig and its value; they are extracted separately
+            remaining.push(arg.clone());
+            remaining.extend(iter.next().cloned());
+            continue;
+        }
+        if !arg.starts_with('-') {
+            before_command = false;
+        }
+        remaining.push(arg.clone());
+    }
+    
+    (options, remaining)
+}
+
 fn main() {
+    // Invoked by cargo as RUSTC_WRAPPER (see wrapper.rs)
+    if wrapper::is_wrapper_invocation() {
+        wrapper::run();
+    }
+    
     let args: Vec<String> = env::args().collect();
     
     // Handle both `cargo-kbuild` and `cargo kbuild` invocation patterns
@@ -587,30 +1583,47 @@ fn main() {
     if is_cargo_subcommand {
         match Cargo::try_parse() {
             Ok(Cargo::Kbuild(kbuild)) => {
-                let workspace_root = env::current_dir()
-                    .expect("Failed to get current directory");
-                
-                let kconfig_path = if kbuild.kconfig.is_absolute() {
-                    kbuild.kconfig
-                } else {
-                    workspace_root.join(kbuild.kconfig)
+                // Arguments for cargo, which may select packages or a manifest
+                let cargo_args: &[String] = match &kbuild.command {
+                    Some(KbuildSubcommand::Build { args })
+                    | Some(KbuildSubcommand::Test { args })
+                    | Some(KbuildSubcommand::Run { args })
+                    | Some(KbuildSubcommand::Check { args })
+                    | Some(KbuildSubcommand::Clippy { args }) => args,
+                    Some(KbuildSubcommand::External(args)) => args.get(1..).unwrap_or_default(),
+                    _ => &[],
                 };
                 
-                match kbuild.command {
+                let invocation = locate_or_exit(
+                    kbuild.directory.clone(),
+                    kbuild.manifest_path.clone(),
+                    kbuild.kconfig.clone(),
+                    kbuild.force,
+                    kbuild.strict,
+                    cargo_args,
+                );
+                
+                match &kbuild.command {
                     Some(KbuildSubcommand::Build { args }) => {
-                        run_cargo_with_kbuild(&workspace_root, &kconfig_path, "build", &args);
+                        run_cargo_with_kbuild(&invocation, "build", args);
                     }
                     Some(KbuildSubcommand::Test { args }) => {
-                        run_cargo_with_kbuild(&workspace_root, &kconfig_path, "test", &args);
+                        run_cargo_with_kbuild(&invocation, "test", args);
                     }
                     Some(KbuildSubcommand::Run { args }) => {
-                        run_cargo_with_kbuild(&workspace_root, &kconfig_path, "run", &args);
+                        run_cargo_with_kbuild(&invocation, "run", args);
                     }
                     Some(KbuildSubcommand::Check { args }) => {
-                        run_cargo_with_kbuild(&workspace_root, &kconfig_path, "check", &args);
+                        run_cargo_with_kbuild(&invocation, "check", args);
                     }
                     Some(KbuildSubcommand::Clippy { args }) => {
-                        run_cargo_with_kbuild(&workspace_root, &kconfig_path, "clippy", &args);
+                        run_cargo_with_kbuild(&invocation, "clippy", args);
+                    }
+                    Some(KbuildSubcommand::IdeSetup) => {
+                        run_ide_setup(&invocation);
+                    }
+                    Some(KbuildSubcommand::RustProject) => {
+                        run_rust_project(&invocation);
                     }
                     Some(KbuildSubcommand::External(args)) => {
                         if args.is_empty() {
@@ -620,7 +1633,7 @@ fn main() {
                         }
                         let cmd = &args[0];
                         let cmd_args = &args[1..];
-                        run_cargo_with_kbuild(&workspace_root, &kconfig_path, cmd, cmd_args);
+                        run_cargo_with_kbuild(&invocation, cmd, cmd_args);
                     }
                     None => {
                         print_help();
@@ -648,20 +1661,27 @@ fn main() {
         process::exit(1);
     }
     
-    let workspace_root = env::current_dir()
-        .expect("Failed to get current directory");
-    
-    // Extract --kconfig if present
-    let (kconfig_path, remaining_args) = extract_kconfig_arg(command_args);
-    let kconfig_path = kconfig_path
-        .unwrap_or_else(|| workspace_root.join(".config"));
+    // Extract --force, --strict, -C, --manifest-path and --kconfig if present
+    let (options, command_args) = extract_leading_options(command_args);
+    let (kconfig_path, remaining_args) = extract_kconfig_arg(&command_args);
+    let cargo_args = remaining_args.get(1..).unwrap_or_default();
+    let locate = || locate_or_exit(
+        options.directory.clone(),
+        options.manifest_path.clone(),
+        kconfig_path.clone(),
+        options.force,
+        options.strict,
+        cargo_args,
+    );
     
-    match remaining_args.get(0).map(|s| s.as_str()) {
+    match remaining_args.first().map(|s| s.as_str()) {
         Some("--help") | Some("-h") | Some("help") => print_help(),
         Some("--version") | Some("-v") | Some("version") => print_version(),
+        Some("ide-setup") => run_ide_setup(&locate()),
+        Some("rust-project") => run_rust_project(&locate()),
         Some(cmd) => {
             // Forward ANY command to cargo with kbuild config
-            run_cargo_with_kbuild(&workspace_root, &kconfig_path, cmd, &remaining_args[1..]);
+            run_cargo_with_kbuild(&locate(), cmd, cargo_args);
         }
         None => {
             // If only --kconfig was provided, show help
@@ -0,0 +1,191 @@
+// Reading Cargo.toml files directly, when `cargo metadata` is unavailable
+//
+// Member manifests commonly inherit fields from the workspace
+// (`version.workspace = true`, `serde = { workspace = true }`). cargo
+// resolves those itself; here they are filled in from `[workspace.package]`
+// and `[workspace.dependencies]` the same way before the manifest is used.
+
+use std::fs;
+use std::path::Path;
+
+/// Dependency tables that may inherit from `[workspace.dependencies]`
+const DEPENDENCY_TABLES: &[&str] = &["dependencies", "dev-dependencies", "build-dependencies"];
+
+/// Read a manifest and resolve its workspace-inherited fields
+///
+/// `workspace_toml` is the parsed root manifest, `workspace_root` its directory.
+pub fn read(path: &Path, workspace_toml: &toml::Value, workspace_root: &Path) -> Result<toml::Value, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let mut manifest: toml::Value = toml::from_str(&content)
+        .map_err(|e| format!("Failed to parse {}: {}", path.display(), e))?;
+
+    let workspace = workspace_toml.get("workspace");
+    inherit_package_fields(&mut manifest, workspace.and_then(|w| w.get("package")))
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    inherit_dependencies(&mut manifest, workspace.and_then(|w| w.get("dependencies")), workspace_root)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    Ok(manifest)
+}
+
+/// Whether a value is `{ workspace = true, ... }`
+fn is_inherited(value: &toml::Value) -> bool {
+    value.get("workspace").and_then(|w| w.as_bool()).unwrap_or(false)
+}
+
+/// `[package]` fields written as `field.workspace = true`
+fn inherit_package_fields(manifest: &mut toml::Value, shared: Option<&toml::Value>) -> Result<(), String> {
+    let package = match manifest.get_mut("package").and_then(|p| p.as_table_mut()) {
+        Some(package) => package,
+        None => return Ok(()),
+    };
+
+    for (field, value) in package.iter_mut() {
+        if !is_inherited(value) {
+            continue;
+        }
+        *value = shared
+            .and_then(|s| s.get(field))
+            .cloned()
+            .ok_or_else(|| format!("`{}.workspace = true` but [workspace.package] has no `{}`", field, field))?;
+    }
+    Ok(())
+}
+
+/// Dependencies written as `name = { workspace = true, ... }`
+///
+/// The workspace entry is used with the member's `features` added and its
+/// `optional` applied, as cargo does. Paths are resolved against the
+/// workspace root and stored as absolute paths.
+fn inherit_dependencies(
+    manifest: &mut toml::Value,
+    shared: Option<&toml::Value>,
+    workspace_root: &Path,
+) -> Result<(), String> {
+    for table_name in DEPENDENCY_TABLES {
+        let table = match manifest.get_mut(*table_name).and_then(|t| t.as_table_mut()) {
+            Some(table) => table,
+            None => continue,
+        };
+
+        for (name, value) in table.iter_mut() {
+            if !is_inherited(value) {
+                continue;
+            }
+            let mut resolved = match shared.and_then(|s| s.get(name)) {
+                Some(toml::Value::String(version)) => {
+                    let mut table = toml::Table::new();
+                    table.insert("version".to_string(), toml::Value::String(version.clone()));
+                    table
+                }
+                Some(toml::Value::Table(table)) => table.clone(),
+                _ => {
+                    return Err(format!(
+                        "dependency `{}` has `workspace = true` but [workspace.dependencies] has no `{}`",
+                        name, name
+                    ))
+                }
+            };
+
+            if let Some(path) = resolved.get("path").and_then(|p| p.as_str()) {
+                let absolute = workspace_root.join(path).display().to_string();
+                resolved.insert("path".to_string(), toml::Value::String(absolute));
+            }
+            if let Some(features) = value.get("features").and_then(|f| f.as_array()) {
+                let merged = resolved
+                    .entry("features")
+                    .or_insert_with(|| toml::Value::Array(Vec::new()));
+                if let Some(merged) = merged.as_array_mut() {
+                    merged.extend(features.iter().cloned());
+                }
+            }
+            if let Some(optional) = value.get("optional") {
+                resolved.insert("optional".to_string(), optional.clone());
+            }
+
+            *value = toml::Value::Table(resolved);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve(member: &str, workspace: &str) -> Result<toml::Value, String> {
+        let mut manifest: toml::Value = toml::from_str(member).unwrap();
+        let workspace: toml::Value = toml::from_str(workspace).unwrap();
+        let workspace = workspace.get("workspace");
+        inherit_package_fields(&mut manifest, workspace.and_then(|w| w.get("package")))?;
+        inherit_dependencies(&mut manifest, workspace.and_then(|w| w.get("dependencies")), Path::new("/ws"))?;
+        Ok(manifest)
+    }
+
+    #[test]
+    fn inherits_package_fields() {
+        let manifest = resolve(
+            "[package]\nname = \"net\"\nversion.workspace = true\n",
+            "[workspace.package]\nversion = \"0.3.0\"\n",
+        )
+        .unwrap();
+        assert_eq!(manifest["package"]["version"].as_str(), Some("0.3.0"));
+        assert_eq!(manifest["package"]["name"].as_str(), Some("net"));
+    }
+
+    #[test]
+    fn inherits_dependencies_with_member_features() {
+        let manifest = resolve(
+            r#"
+            [dependencies]
+            log = { workspace = true, features = ["std"], optional = true }
+            serde.workspace = true
+
+            [build-dependencies]
+            kbuild_build.workspace = true
+            "#,
+            r#"
+            [workspace.dependencies]
+            log = { version = "0.4", features = ["max_level_debug"] }
+            serde = "1"
+            kbuild_build = { path = "crates/kbuild_build" }
+            "#,
+        )
+        .unwrap();
+
+        let log = &manifest["dependencies"]["log"];
+        assert_eq!(log["version"].as_str(), Some("0.4"));
+        let features: Vec<_> = log["features"].as_array().unwrap().iter().filter_map(|f| f.as_str()).collect();
+        assert_eq!(features, ["max_level_debug", "std"]);
+        assert_eq!(log["optional"].as_bool(), Some(true));
+        assert!(log.get("workspace").is_none());
+
+        assert_eq!(manifest["dependencies"]["serde"]["version"].as_str(), Some("1"));
+        let path = manifest["build-dependencies"]["kbuild_build"]["path"].as_str().unwrap();
+        assert_eq!(Path::new(path), Path::new("/ws/crates/kbuild_build"));
+    }
+
+    #[test]
+    fn reports_missing_package_field() {
+        let err = resolve("[package]\nname = \"net\"\nedition.workspace = true\n", "[workspace]\n").unwrap_err();
+        assert!(err.contains("[workspace.package] has no `edition`"), "{}", err);
+    }
+
+    #[test]
+    fn reports_missing_workspace_dependency() {
+        let err = resolve(
+            "[dependencies]\nlog = { workspace = true }\n",
+            "[workspace.dependencies]\nserde = \"1\"\n",
+        )
+        .unwrap_err();
+        assert!(err.contains("[workspace.dependencies] has no `log`"), "{}", err);
+    }
+
+    #[test]
+    fn read_reports_the_manifest_path() {
+        let err = read(Path::new("/nonexistent/Cargo.toml"), &toml::Value::Table(Default::default()), Path::new("/"))
+            .unwrap_err();
+        assert!(err.starts_with("Failed to read /nonexistent/Cargo.toml"), "{}", err);
+    }
+}
@@ -0,0 +1,142 @@
+// Workspace discovery through `cargo metadata`
+//
+// Reading Cargo.toml files by hand misses most of what cargo resolves: glob
+// members (`crates/*`), `exclude`, `default-members`, path dependencies
+// outside the workspace and the dependency graph itself. `cargo metadata`
+// reports all of it, so cargo-kbuild works on the packages, targets,
+// features and edges cargo actually builds.
+
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// Output of `cargo metadata --format-version 1`
+#[derive(Debug, Deserialize)]
+pub struct CargoMetadata {
+    pub packages: Vec<Package>,
+    pub workspace_members: Vec<String>,
+    /// Missing in cargo versions before 1.71
+    #[serde(default)]
+    pub workspace_default_members: Option<Vec<String>>,
+    pub resolve: Option<Resolve>,
+    /// `[workspace.metadata]`
+    #[serde(default)]
+    pub metadata: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Package {
+    pub id: String,
+    pub name: String,
+    pub manifest_path: PathBuf,
+    #[serde(default)]
+    pub features: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    pub targets: Vec<Target>,
+    /// Dependencies as declared in Cargo.toml
+    #[serde(default)]
+    pub dependencies: Vec<Dependency>,
+    /// `[package.metadata]`
+    #[serde(default)]
+    pub metadata: Option<Value>,
+}
+
+/// A dependency declaration
+#[derive(Debug, Deserialize)]
+pub struct Dependency {
+    /// Name of the package depended on
+    pub name: String,
+    /// Name the dependency is declared under (`net = { package = "kernel_net" }`)
+    pub rename: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Target {
+    pub name: String,
+    /// `lib`, `bin`, `proc-macro`, `test`, `custom-build`, ...
+    pub kind: Vec<String>,
+    pub src_path: PathBuf,
+    pub edition: String,
+}
+
+impl Target {
+    /// Library-like targets that dependents link against
+    pub fn is_lib(&self) -> bool {
+        self.kind.iter().any(|k| matches!(k.as_str(), "lib" | "rlib" | "dylib" | "proc-macro"))
+    }
+
+    pub fn is_bin(&self) -> bool {
+        self.kind.iter().any(|k| k == "bin")
+    }
+
+    pub fn is_proc_macro(&self) -> bool {
+        self.kind.iter().any(|k| k == "proc-macro")
+    }
+}
+
+/// The resolved dependency graph
+#[derive(Debug, Deserialize)]
+pub struct Resolve {
+    pub nodes: Vec<Node>,
+}
+
+impl Resolve {
+    pub fn node(&self, id: &str) -> Option<&Node> {
+        self.nodes.iter().find(|n| n.id == id)
+    }
+}
+
+/// A package in the graph, with its enabled features and edges
+#[derive(Debug, Deserialize)]
+pub struct Node {
+    pub id: String,
+    #[serde(default)]
+    pub deps: Vec<NodeDep>,
+    /// Features enabled for this package
+    #[serde(default)]
+    pub features: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NodeDep {
+    /// Name the dependency is known by in code (renames applied, `-` as `_`)
+    pub name: String,
+    pub pkg: String,
+    #[serde(default)]
+    pub dep_kinds: Vec<DepKind>,
+}
+
+impl NodeDep {
+    /// Whether this is a normal (not dev or build) dependency
+    pub fn is_normal(&self) -> bool {
+        self.dep_kinds.is_empty() || self.dep_kinds.iter().any(|k| k.kind.is_none())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DepKind {
+    /// `None` for normal dependencies, `dev` or `build` otherwise
+    pub kind: Option<String>,
+}
+
+/// Run `cargo metadata` in `workspace_root`, resolving with `features` enabled
+pub fn load(workspace_root: &Path, features: &[String]) -> Result<CargoMetadata, String> {
+    let mut cmd = process::Command::new("cargo");
+    cmd.args(["metadata", "--format-version", "1"]);
+    if !features.is_empty() {
+        cmd.arg("--features").arg(features.join(","));
+    }
+    cmd.current_dir(workspace_root);
+
+    let output = cmd.output()
+        .map_err(|e| format!("Failed to run cargo metadata: {}", e))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("cargo metadata failed: {}", stderr.trim()));
+    }
+
+    serde_json::from_slice(&output.stdout)
+        .map_err(|e| format!("Failed to parse cargo metadata output: {}", e))
+}
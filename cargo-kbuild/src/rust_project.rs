@@ -0,0 +1,177 @@
+// rust-project.json for builds that invoke rustc directly
+//
+// rust-analyzer normally learns the crate graph from `cargo metadata`. A
+// Makefile driving rustc has no such source, so like Linux's
+// `make rust-analyzer` (scripts/generate_rust_analyzer.py) cargo-kbuild
+// describes the graph itself: one entry per lib and bin target of every
+// workspace crate, with its edition, its dependencies on other workspace
+// crates as resolved by `cargo metadata`, the cfgs of the current .config
+// and the environment the kbuild crates read at compile time:
+// CONFIG_RS_PATH for `kbuild_config`, and for crates listing `symbols` an
+// OUT_DIR holding their config module as their build script would write
+// it (target/kbuild/out/<crate>/kbuild_symbols.rs).
+//
+// Dependencies outside the workspace (crates.io, git) are not described;
+// they are reported instead. std, core and alloc come from the sysroot of
+// `rustc --print sysroot`.
+
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use super::metadata::{CargoMetadata, Target};
+use super::{CrateInfo, Workspace};
+
+/// Output file, relative to the workspace root
+pub const PROJECT_FILE: &str = "rust-project.json";
+
+/// One root module of the crate graph
+struct Entry<'a> {
+    krate: &'a CrateInfo,
+    target: &'a Target,
+}
+
+impl Entry<'_> {
+    /// Crate name as seen by dependents and in diagnostics
+    fn name(&self) -> String {
+        if self.target.is_lib() {
+            self.target.name.replace('-', "_")
+        } else {
+            self.target.name.clone()
+        }
+    }
+}
+
+/// Summary of a generated rust-project.json
+pub struct ProjectSummary {
+    pub path: PathBuf,
+    pub crates: usize,
+    pub changed: bool,
+    /// Dependencies left out of the graph, as `crate -> dependency`
+    pub external: Vec<String>,
+}
+
+/// Sysroot of the rustc in use, so rust-analyzer can load std
+fn sysroot() -> Option<String> {
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let output = process::Command::new(rustc).args(["--print", "sysroot"]).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8(output.stdout).ok()?.trim().to_string()).filter(|s| !s.is_empty())
+}
+
+/// Place a crate's config module where `include!(concat!(env!("OUT_DIR"), ...))`
+/// finds it, returning the directory to use as OUT_DIR
+fn crate_out_dir(kbuild_dir: &Path, krate: &CrateInfo) -> Result<PathBuf, String> {
+    let generated = kbuild_dir.join("crates").join(format!("{}.rs", krate.name));
+    let content = fs::read_to_string(&generated)
+        .map_err(|e| format!("Failed to read {}: {}", generated.display(), e))?;
+
+    let out_dir = kbuild_dir.join("out").join(&krate.name);
+    fs::create_dir_all(&out_dir)
+        .map_err(|e| format!("Failed to create {}: {}", out_dir.display(), e))?;
+    super::write_if_changed(&out_dir.join(kbuild_build::CRATE_MODULE), &content)?;
+    Ok(out_dir)
+}
+
+/// Write rust-project.json for the workspace and configuration
+///
+/// `graph` is `cargo metadata` resolved with the features cargo-kbuild
+/// enables, so each crate gets the features and edges cargo would build.
+pub fn generate(
+    workspace_root: &Path,
+    workspace: &Workspace,
+    graph: &CargoMetadata,
+    config: &BTreeMap<String, String>,
+) -> Result<ProjectSummary, String> {
+    let resolve = graph.resolve.as_ref().ok_or("cargo metadata returned no dependency graph")?;
+    let kbuild_dir = workspace_root.join("target/kbuild");
+    let kbuild_cfgs: Vec<String> = config
+        .iter()
+        .filter_map(|(name, value)| super::symbols::cfg_flag(name, value))
+        .collect();
+
+    let mut entries = Vec::new();
+    for krate in &workspace.crates {
+        let package = graph.packages.iter().find(|p| p.id == krate.id)
+            .ok_or_else(|| format!("Package {} missing from cargo metadata", krate.name))?;
+        for target in package.targets.iter().filter(|t| t.is_lib() || t.is_bin()) {
+            entries.push(Entry { krate, target });
+        }
+    }
+    let lib_index = |id: &str| entries.iter().position(|e| e.target.is_lib() && e.krate.id == id);
+
+    let mut external = BTreeSet::new();
+    let mut crates = Vec::new();
+    for entry in &entries {
+        let krate = entry.krate;
+        let node = resolve.node(&krate.id);
+
+        let mut deps = Vec::new();
+        for dep in node.into_iter().flat_map(|n| &n.deps).filter(|d| d.is_normal()) {
+            match lib_index(&dep.pkg) {
+                Some(index) => deps.push(json!({ "crate": index, "name": dep.name })),
+                None => {
+                    external.insert(format!("{} -> {}", krate.name, dep.name));
+                }
+            }
+        }
+        // A bin links against its own package's lib
+        if !entry.target.is_lib() {
+            if let Some(index) = lib_index(&krate.id) {
+                deps.push(json!({ "crate": index, "name": entries[index].name() }));
+            }
+        }
+
+        let is_kbuild = workspace.is_kbuild_member(krate);
+        let mut cfg = if is_kbuild { kbuild_cfgs.clone() } else { Vec::new() };
+        for feature in node.into_iter().flat_map(|n| &n.features) {
+            cfg.push(format!("feature={:?}", feature));
+        }
+
+        let mut env = Map::new();
+        env.insert("CARGO_PKG_NAME".to_string(), json!(krate.name));
+        if is_kbuild {
+            env.insert("KBUILD_OUT_DIR".to_string(), json!(kbuild_dir.display().to_string()));
+            env.insert("CONFIG_RS_PATH".to_string(), json!(kbuild_dir.join("config.rs").display().to_string()));
+            if krate.symbols.is_some() {
+                let out_dir = crate_out_dir(&kbuild_dir, krate)?;
+                env.insert("OUT_DIR".to_string(), json!(out_dir.display().to_string()));
+            }
+        }
+
+        crates.push(json!({
+            "display_name": entry.name(),
+            "root_module": entry.target.src_path.display().to_string(),
+            "edition": entry.target.edition,
+            "deps": deps,
+            "cfg": cfg,
+            "env": Value::Object(env),
+            "is_workspace_member": true,
+            "is_proc_macro": entry.target.is_proc_macro(),
+        }));
+    }
+
+    let mut project = Map::new();
+    if let Some(sysroot) = sysroot() {
+        project.insert("sysroot".to_string(), json!(sysroot));
+    }
+    project.insert("crates".to_string(), Value::Array(crates));
+
+    let path = workspace_root.join(PROJECT_FILE);
+    let mut content = serde_json::to_string_pretty(&Value::Object(project))
+        .map_err(|e| format!("Failed to serialize {}: {}", path.display(), e))?;
+    content.push('\n');
+    let changed = super::write_if_changed(&path, &content)?;
+
+    Ok(ProjectSummary {
+        path,
+        crates: entries.len(),
+        changed,
+        external: external.into_iter().collect(),
+    })
+}
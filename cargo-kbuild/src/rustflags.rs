@@ -0,0 +1,287 @@
+// Delivery of kbuild's rustc flags without discarding the user's own
+//
+// Cargo takes rustflags from exactly one of these sources, in order:
+//   1. CARGO_ENCODED_RUSTFLAGS
+//   2. RUSTFLAGS
+//   3. all matching target.<triple>.rustflags / target.'cfg(..)'.rustflags
+//   4. build.rustflags
+// Setting RUSTFLAGS therefore silently disables every rustflags entry in
+// .cargo/config.toml. Instead, kbuild flags are appended to whichever source
+// cargo would actually use.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// Separator used by CARGO_ENCODED_RUSTFLAGS
+const ENCODED_SEPARATOR: char = '\x1f';
+
+/// How kbuild's flags reach rustc
+#[derive(Debug)]
+pub enum Delivery {
+    /// User flags came from the environment: replace them with the merged
+    /// list in CARGO_ENCODED_RUSTFLAGS
+    Environment { source: &'static str, flags: Vec<String> },
+    /// build.rustflags is a string, which cannot carry a kbuild flag that
+    /// contains whitespace: pass the merged list in CARGO_ENCODED_RUSTFLAGS
+    Encoded { flags: Vec<String> },
+    /// No flags in the environment: add `--config` overrides that cargo merges
+    /// with the rustflags from its config files
+    Config { args: Vec<String>, target_keys: Vec<String> },
+}
+
+impl Delivery {
+    /// Apply the delivery to the cargo command.
+    ///
+    /// `--config` arguments must precede the cargo subcommand, so they are
+    /// returned for the caller to place in front of it.
+    pub fn apply(&self, cmd: &mut process::Command) -> Vec<String> {
+        match self {
+            Delivery::Environment { flags, .. } | Delivery::Encoded { flags } => {
+                let encoded: Vec<&str> = flags.iter().map(|f| f.as_str()).collect();
+                cmd.env("CARGO_ENCODED_RUSTFLAGS", encoded.join(&ENCODED_SEPARATOR.to_string()));
+                Vec::new()
+            }
+            Delivery::Config { args, .. } => args.clone(),
+        }
+    }
+
+    /// One-line description for the build summary
+    pub fn describe(&self) -> String {
+        match self {
+            Delivery::Environment { source, .. } => {
+                format!("merged with {} from the environment", source)
+            }
+            Delivery::Encoded { .. } => {
+                "merged with string-form build.rustflags, passed via CARGO_ENCODED_RUSTFLAGS".to_string()
+            }
+            Delivery::Config { target_keys, .. } if target_keys.is_empty() => {
+                "passed via --config, merged with build.rustflags".to_string()
+            }
+            Delivery::Config { target_keys, .. } => format!(
+                "passed via --config, merged with build.rustflags and target rustflags ({})",
+                target_keys.join(", ")
+            ),
+        }
+    }
+}
+
+/// Decide how to deliver `kbuild_flags` (one rustc argument per element)
+///
+/// `cwd` is the directory cargo runs in, where its config file lookup starts.
+pub fn plan(cwd: &Path, kbuild_flags: &[String]) -> Result<Delivery, String> {
+    // 1./2. Flags from the environment take precedence over all config files
+    let from_env = match env::var("CARGO_ENCODED_RUSTFLAGS") {
+        Ok(encoded) => Some((
+            "CARGO_ENCODED_RUSTFLAGS",
+            encoded
+                .split(ENCODED_SEPARATOR)
+                .filter(|f| !f.is_empty())
+                .map(String::from)
+                .collect::<Vec<_>>(),
+        )),
+        Err(_) => env::var("RUSTFLAGS").ok().map(|flags| {
+            ("RUSTFLAGS", flags.split_whitespace().map(String::from).collect())
+        }),
+    };
+
+    if let Some((source, mut flags)) = from_env {
+        flags.extend(kbuild_flags.iter().cloned());
+        return Ok(Delivery::Environment { source, flags });
+    }
+
+    // 3./4. Config files: extend build.rustflags (used when no target entry
+    // matches) and every target.*.rustflags entry (one of which may match)
+    let existing = ConfigRustflags::read(cwd)?;
+
+    // A string entry splits on whitespace, so a flag like `NAME="my board"`
+    // cannot be added to it. With no target entries build.rustflags is what
+    // cargo would use, and the merged list can go to CARGO_ENCODED_RUSTFLAGS.
+    let build_string = existing.build.as_ref().and_then(|b| b.as_str());
+    let has_whitespace = kbuild_flags.iter().any(|f| f.contains(char::is_whitespace));
+    if let (Some(flags), true, true) = (build_string, has_whitespace, existing.targets.is_empty()) {
+        let mut merged: Vec<String> = flags.split_whitespace().map(String::from).collect();
+        merged.extend(kbuild_flags.iter().cloned());
+        return Ok(Delivery::Encoded { flags: merged });
+    }
+
+    let mut args = Vec::new();
+    let mut target_keys = Vec::new();
+
+    args.push("--config".to_string());
+    args.push(format!(
+        "build.rustflags={}",
+        merged_value("build.rustflags", existing.build.as_ref(), kbuild_flags)?
+    ));
+
+    for (key, flags) in &existing.targets {
+        let config_key = format!("target.{}.rustflags", toml_key(key));
+        args.push("--config".to_string());
+        args.push(format!("{}={}", config_key, merged_value(&config_key, Some(flags), kbuild_flags)?));
+        target_keys.push(key.clone());
+    }
+
+    Ok(Delivery::Config { args, target_keys })
+}
+
+/// Render a TOML value for `--config` that merges with `existing`
+///
+/// Cargo concatenates arrays from different sources, so without a string
+/// entry ours is passed as an array. Cargo refuses to merge an array into a
+/// string entry, and a string from `--config` replaces the file's string, so
+/// string-form rustflags are re-emitted as a string with ours appended.
+fn merged_value(key: &str, existing: Option<&toml::Value>, kbuild_flags: &[String]) -> Result<String, String> {
+    match existing.and_then(|v| v.as_str()) {
+        Some(flags) => {
+            if let Some(flag) = kbuild_flags.iter().find(|f| f.contains(char::is_whitespace)) {
+                return Err(format!(
+                    "{} is a string in .cargo/config.toml, which cannot carry the flag '{}' \
+                     alongside target rustflags; write it as an array (or use cfg-mode = \"wrapper\")",
+                    key, flag
+                ));
+            }
+            let mut merged: Vec<&str> = flags.split_whitespace().collect();
+            merged.extend(kbuild_flags.iter().map(|f| f.as_str()));
+            Ok(toml::Value::String(merged.join(" ")).to_string())
+        }
+        None => Ok(toml_array(kbuild_flags)),
+    }
+}
+
+fn toml_array(flags: &[String]) -> String {
+    let items: Vec<String> = flags
+        .iter()
+        .map(|f| toml::Value::String(f.clone()).to_string())
+        .collect();
+    format!("[{}]", items.join(", "))
+}
+
+/// Quote a TOML key if it is not a valid bare key (e.g. `cfg(unix)`)
+fn toml_key(key: &str) -> String {
+    if !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        key.to_string()
+    } else {
+        toml::Value::String(key.to_string()).to_string()
+    }
+}
+
+/// Cargo config files that apply to cargo running in `cwd`
+///
+/// Cargo reads `.cargo/config.toml` (or legacy `.cargo/config`) in the
+/// directory and every parent, plus `$CARGO_HOME/config.toml`.
+fn config_files(cwd: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+
+    let mut dir = Some(cwd);
+    while let Some(current) = dir {
+        for name in ["config.toml", "config"] {
+            let path = current.join(".cargo").join(name);
+            if path.is_file() {
+                files.push(path);
+                break;
+            }
+        }
+        dir = current.parent();
+    }
+
+    let cargo_home = env::var_os("CARGO_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cargo")));
+    if let Some(cargo_home) = cargo_home {
+        let path = cargo_home.join("config.toml");
+        if path.is_file() && !files.contains(&path) {
+            files.push(path);
+        }
+    }
+
+    files
+}
+
+/// rustflags entries found in the applicable config files
+///
+/// Each entry keeps its value from the highest-priority file, which is
+/// enough to tell string-form from array-form rustflags.
+struct ConfigRustflags {
+    build: Option<toml::Value>,
+    targets: Vec<(String, toml::Value)>,
+}
+
+impl ConfigRustflags {
+    fn read(cwd: &Path) -> Result<ConfigRustflags, String> {
+        let mut build = None;
+        let mut targets: Vec<(String, toml::Value)> = Vec::new();
+
+        for path in config_files(cwd) {
+            let content = fs::read_to_string(&path)
+                .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+            let value: toml::Value = toml::from_str(&content)
+                .map_err(|e| format!("Failed to parse {}: {}", path.display(), e))?;
+
+            if build.is_none() {
+                build = value.get("build").and_then(|b| b.get("rustflags")).cloned();
+            }
+
+            let target_tables = value.get("target").and_then(|t| t.as_table());
+            for (key, table) in target_tables.into_iter().flatten() {
+                if let Some(flags) = table.get("rustflags") {
+                    if !targets.iter().any(|(k, _)| k == key) {
+                        targets.push((key.clone(), flags.clone()));
+                    }
+                }
+            }
+        }
+
+        Ok(ConfigRustflags { build, targets })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flags(flags: &[&str]) -> Vec<String> {
+        flags.iter().map(|f| f.to_string()).collect()
+    }
+
+    #[test]
+    fn merged_value_without_entry_is_an_array() {
+        let value = merged_value("build.rustflags", None, &flags(&["--cfg", "SMP"])).unwrap();
+        assert_eq!(value, r#"["--cfg", "SMP"]"#);
+    }
+
+    #[test]
+    fn merged_value_appends_to_a_string_entry() {
+        let existing = toml::Value::String("-C debuginfo=1".to_string());
+        let value = merged_value("build.rustflags", Some(&existing), &flags(&["--cfg", "SMP"])).unwrap();
+        assert_eq!(value, r#""-C debuginfo=1 --cfg SMP""#);
+    }
+
+    #[test]
+    fn merged_value_rejects_whitespace_in_a_string_entry() {
+        let existing = toml::Value::String("-C debuginfo=1".to_string());
+        let err = merged_value("target.x.rustflags", Some(&existing), &flags(&["--cfg", r#"NAME="my board""#]))
+            .unwrap_err();
+        assert!(err.contains("target.x.rustflags"), "{}", err);
+        assert!(err.contains("my board"), "{}", err);
+    }
+
+    #[test]
+    fn merged_value_keeps_arrays_as_arrays() {
+        let existing = toml::Value::Array(vec![toml::Value::String("-Dwarnings".to_string())]);
+        let value = merged_value("build.rustflags", Some(&existing), &flags(&["--cfg", r#"NAME="my board""#])).unwrap();
+        let parsed: toml::Table = toml::from_str(&format!("v = {}", value)).unwrap();
+        let expected = toml::Value::Array(vec![
+            toml::Value::String("--cfg".to_string()),
+            toml::Value::String(r#"NAME="my board""#.to_string()),
+        ]);
+        assert_eq!(parsed["v"], expected);
+    }
+
+    #[test]
+    fn toml_key_quotes_only_when_needed() {
+        assert_eq!(toml_key("x86_64-unknown-linux-gnu"), "x86_64-unknown-linux-gnu");
+        assert_eq!(toml_key("cfg(unix)"), r#""cfg(unix)""#);
+        assert_eq!(toml_key(""), r#""""#);
+    }
+}
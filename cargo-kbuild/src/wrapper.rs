@@ -0,0 +1,286 @@
+// rustc wrapper mode: apply kbuild cfgs only to kbuild-enabled crates
+//
+// Flags in RUSTFLAGS reach every crate in the graph, including third-party
+// dependencies, and any change to them rebuilds the whole tree. Instead,
+// cargo-kbuild registers itself as RUSTC_WRAPPER and appends the kbuild flags
+// only when rustc compiles a crate with `[package.metadata.kbuild] enabled`.
+//
+// Each crate gets its own flags file, holding only the cfgs of the symbols
+// it uses (see fixdep.rs). Files are keyed by manifest directory as well as
+// name, since two packages in the graph may share a name. Cargo does not know about flags added by a
+// wrapper, so the flags file is also appended to the crate's dep-info. Cargo
+// then treats it as a source file: the crate is rebuilt exactly when its
+// flags file changes (it is only rewritten when its content changes).
+
+use std::collections::BTreeSet;
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// Directory of the per-crate flags files; its presence marks a wrapper invocation
+pub const FLAGS_ENV: &str = "KBUILD_RUSTC_WRAPPER";
+/// Manifest directories of kbuild-enabled crates (joined like PATH)
+pub const CRATES_ENV: &str = "KBUILD_WRAPPER_CRATES";
+/// A RUSTC_WRAPPER the user had configured (e.g. sccache), chained to
+pub const INNER_WRAPPER_ENV: &str = "KBUILD_INNER_RUSTC_WRAPPER";
+
+/// rustc flags for one kbuild-enabled crate
+pub struct CrateFlags {
+    pub name: String,
+    /// Directory containing the crate's Cargo.toml
+    pub dir: PathBuf,
+    pub flags: Vec<String>,
+}
+
+/// Write the flags files and configure `cmd` to run cargo through the wrapper
+///
+/// `all_flags` go to target/kbuild/rustc-cfgs for tools outside cargo
+/// (e.g. Makefiles driving rustc directly); each crate's own flags go to
+/// target/kbuild/cfgs/<crate>-<key>. Returns the crates whose flags file was
+/// created or changed, i.e. the crates cargo will rebuild.
+pub fn configure(
+    workspace_root: &Path,
+    cmd: &mut process::Command,
+    all_flags: &[String],
+    crates: &[CrateFlags],
+) -> Result<Vec<String>, String> {
+    super::write_if_changed(&workspace_root.join("target/kbuild/rustc-cfgs"), &render_flags(all_flags))?;
+
+    let flags_dir = workspace_root.join("target/kbuild/cfgs");
+    fs::create_dir_all(&flags_dir)
+        .map_err(|e| format!("Failed to create {}: {}", flags_dir.display(), e))?;
+    let mut changed = Vec::new();
+    for crate_flags in crates {
+        let path = flags_path(&flags_dir, &crate_flags.name, &canonical(&crate_flags.dir));
+        if super::write_if_changed(&path, &render_flags(&crate_flags.flags))? {
+            changed.push(crate_flags.name.clone());
+        }
+    }
+
+    let exe = env::current_exe()
+        .map_err(|e| format!("Failed to locate cargo-kbuild executable: {}", e))?;
+
+    let dirs: Vec<PathBuf> = crates.iter().map(|c| canonical(&c.dir)).collect();
+    let crate_dirs = env::join_paths(&dirs)
+        .map_err(|e| format!("Invalid crate path for {}: {}", CRATES_ENV, e))?;
+
+    if let Some(inner) = env::var_os("RUSTC_WRAPPER").filter(|w| !w.is_empty()) {
+        cmd.env(INNER_WRAPPER_ENV, inner);
+    }
+    cmd.env("RUSTC_WRAPPER", exe);
+    cmd.env(FLAGS_ENV, &flags_dir);
+    cmd.env(CRATES_ENV, crate_dirs);
+
+    Ok(changed)
+}
+
+/// One flag per line
+fn render_flags(flags: &[String]) -> String {
+    let mut content = String::new();
+    for flag in flags {
+        content.push_str(flag);
+        content.push('\n');
+    }
+    content
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Flags file of the crate `name` in the (canonical) manifest directory `dir`
+///
+/// The name keeps the file recognizable; a 64-bit FNV-1a hash of the
+/// directory tells apart packages of the same name.
+fn flags_path(flags_dir: &Path, name: &str, dir: &Path) -> PathBuf {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in dir.to_string_lossy().bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    flags_dir.join(format!("{}-{:016x}", name, hash))
+}
+
+/// Whether `program` is rustc, or a driver cargo runs in its place
+fn is_rustc(program: &OsStr) -> bool {
+    if env::var_os("RUSTC").is_some_and(|rustc| rustc == program) {
+        return true;
+    }
+    let stem = Path::new(program).file_stem().and_then(|s| s.to_str());
+    matches!(stem, Some("rustc" | "clippy-driver"))
+}
+
+/// Check whether cargo-kbuild was invoked by cargo as RUSTC_WRAPPER
+///
+/// Programs cargo runs inherit FLAGS_ENV (e.g. `cargo kbuild run -p
+/// cargo-kbuild -- build`), so the first argument must name rustc as well.
+pub fn is_wrapper_invocation() -> bool {
+    env::var_os(FLAGS_ENV).is_some() && env::args_os().nth(1).is_some_and(|program| is_rustc(&program))
+}
+
+/// Run as RUSTC_WRAPPER: `cargo-kbuild <rustc> <args...>`
+pub fn run() -> ! {
+    let mut args = env::args_os().skip(1);
+    let rustc = args.next().expect("wrapper invoked without rustc path");
+    let mut rustc_args: Vec<OsString> = args.collect();
+
+    // Cargo hashes `rustc -vV` into every fingerprint. Tagging it makes
+    // artifacts from plain `cargo build` runs (compiled without kbuild cfgs)
+    // stale under the wrapper and vice versa, so the two never get mixed.
+    if rustc_args.len() == 1 && rustc_args[0] == "-vV" {
+        version_query(&rustc);
+    }
+
+    let flags_path = match (env::var_os(FLAGS_ENV), env::var("CARGO_PKG_NAME"), kbuild_crate_dir()) {
+        (Some(flags_dir), Ok(name), Some(dir)) => Some(flags_path(Path::new(&flags_dir), &name, &dir)),
+        _ => None,
+    };
+
+    if let Some(flags_path) = &flags_path {
+        match fs::read_to_string(flags_path) {
+            Ok(flags) => rustc_args.extend(flags.lines().filter(|l| !l.is_empty()).map(OsString::from)),
+            Err(e) => {
+                eprintln!("❌ cargo-kbuild wrapper: failed to read {}: {}", flags_path.display(), e);
+                process::exit(1);
+            }
+        }
+    }
+
+    let mut cmd = match env::var_os(INNER_WRAPPER_ENV) {
+        Some(inner) => {
+            let mut cmd = process::Command::new(inner);
+            cmd.arg(&rustc);
+            cmd
+        }
+        None => process::Command::new(&rustc),
+    };
+    cmd.args(&rustc_args);
+
+    let status = match cmd.status() {
+        Ok(status) => status,
+        Err(e) => {
+            eprintln!("❌ cargo-kbuild wrapper: failed to run {}: {}", rustc.to_string_lossy(), e);
+            process::exit(1);
+        }
+    };
+
+    if status.success() {
+        if let Some(flags_path) = &flags_path {
+            if let Err(e) = add_dep_info(&rustc_args, flags_path) {
+                eprintln!("❌ cargo-kbuild wrapper: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+
+    process::exit(status.code().unwrap_or(1));
+}
+
+/// Answer `rustc -vV` with rustc's output plus a cargo-kbuild marker line
+fn version_query(rustc: &OsString) -> ! {
+    let mut cmd = match env::var_os(INNER_WRAPPER_ENV) {
+        Some(inner) => {
+            let mut cmd = process::Command::new(inner);
+            cmd.arg(rustc);
+            cmd
+        }
+        None => process::Command::new(rustc),
+    };
+
+    let output = match cmd.arg("-vV").stderr(process::Stdio::inherit()).output() {
+        Ok(output) => output,
+        Err(e) => {
+            eprintln!("❌ cargo-kbuild wrapper: failed to run {}: {}", rustc.to_string_lossy(), e);
+            process::exit(1);
+        }
+    };
+
+    let mut stdout = io::stdout();
+    let _ = stdout.write_all(&output.stdout);
+    if output.status.success() {
+        let _ = writeln!(stdout, "kbuild-wrapper: {}", env!("CARGO_PKG_VERSION"));
+    }
+    let _ = stdout.flush();
+
+    process::exit(output.status.code().unwrap_or(1));
+}
+
+/// Manifest directory of the crate being compiled, if it is kbuild-enabled
+fn kbuild_crate_dir() -> Option<PathBuf> {
+    let manifest_dir = canonical(Path::new(&env::var_os("CARGO_MANIFEST_DIR")?));
+
+    let crates: BTreeSet<PathBuf> = env::var_os(CRATES_ENV)
+        .map(|dirs| env::split_paths(&dirs).collect())
+        .unwrap_or_default();
+
+    Some(manifest_dir).filter(|dir| crates.contains(dir))
+}
+
+/// Value of a rustc option given as `--opt value` or `--opt=value`
+fn option_value<'a>(args: &'a [OsString], name: &str) -> Option<&'a str> {
+    let prefix = format!("{}=", name);
+    let mut iter = args.iter().filter_map(|a| a.to_str());
+    while let Some(arg) = iter.next() {
+        if arg == name {
+            return iter.next();
+        }
+        if let Some(value) = arg.strip_prefix(&prefix) {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Append `dep` to the dependency list of the dep-info file rustc wrote
+///
+/// Cargo reads the first `target: deps...` rule of `<out-dir>/<crate-name><extra-filename>.d`.
+fn add_dep_info(rustc_args: &[OsString], dep: &Path) -> Result<(), String> {
+    let emits_dep_info = option_value(rustc_args, "--emit")
+        .map(|emit| emit.split(',').any(|kind| kind.starts_with("dep-info")))
+        .unwrap_or(false);
+    if !emits_dep_info {
+        return Ok(());
+    }
+
+    let (out_dir, crate_name) = match (
+        option_value(rustc_args, "--out-dir"),
+        option_value(rustc_args, "--crate-name"),
+    ) {
+        (Some(out_dir), Some(crate_name)) => (out_dir, crate_name),
+        _ => return Ok(()),
+    };
+
+    let extra_filename = rustc_args
+        .iter()
+        .filter_map(|a| a.to_str())
+        .find_map(|a| a.strip_prefix("extra-filename="))
+        .unwrap_or("");
+
+    let dep_info_path = Path::new(out_dir).join(format!("{}{}.d", crate_name, extra_filename));
+    let content = match fs::read_to_string(&dep_info_path) {
+        Ok(content) => content,
+        Err(_) => return Ok(()),
+    };
+
+    let dep = dep.display().to_string().replace(' ', "\\ ");
+    let mut patched = String::with_capacity(content.len() + dep.len() + 1);
+    let mut added = false;
+    for line in content.lines() {
+        patched.push_str(line);
+        if !added && !line.starts_with('#') && line.contains(": ") {
+            patched.push(' ');
+            patched.push_str(&dep);
+            added = true;
+        }
+        patched.push('\n');
+    }
+
+    if added {
+        fs::write(&dep_info_path, patched)
+            .map_err(|e| format!("Failed to update {}: {}", dep_info_path.display(), e))?;
+    }
+    Ok(())
+}
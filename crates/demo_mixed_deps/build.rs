@@ -0,0 +1,4 @@
+// Place the per-crate config module in OUT_DIR, with or without cargo-kbuild
+fn main() {
+    kbuild_build::crate_module();
+}
@@ -1,4 +1,10 @@
-use kbuild_config::*;
+// Per-crate config module with the symbols declared in Cargo.toml
+// ([package.metadata.kbuild] symbols), written to OUT_DIR by build.rs
+mod config {
+    include!(concat!(env!("OUT_DIR"), "/kbuild_symbols.rs"));
+}
+
+use config::*;
 
 pub fn demo() {
     println!("🎪 [DEMO] Demo Mixed Dependencies");
@@ -0,0 +1,65 @@
+// Reader for .config-format files (.config, defconfigs)
+//
+// Only the lines Kconfig tools write are understood: `NAME=value`
+// assignments and `# NAME is not set` comments. Values are kept raw
+// (`y`, `3`, `"cfs"`); `unquote_string` removes the quoting of strings.
+
+use std::fs;
+use std::path::Path;
+
+/// One symbol line of a .config-format file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assignment {
+    pub name: String,
+    /// Raw value, or `None` for `# NAME is not set`
+    pub value: Option<String>,
+    /// 1-based line number
+    pub line: usize,
+}
+
+/// Parse the symbol lines of a .config-format file, in file order
+pub fn parse(content: &str) -> Vec<Assignment> {
+    let mut assignments = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if let Some(comment) = line.strip_prefix('#') {
+            let name = comment.trim().strip_suffix(" is not set").map(str::trim);
+            if let Some(name) = name.filter(|n| !n.is_empty() && !n.contains(char::is_whitespace)) {
+                assignments.push(Assignment { name: name.to_string(), value: None, line: index + 1 });
+            }
+        } else if let Some((key, value)) = line.split_once('=') {
+            assignments.push(Assignment {
+                name: key.trim().to_string(),
+                value: Some(value.trim().to_string()),
+                line: index + 1,
+            });
+        }
+    }
+    assignments
+}
+
+/// Read and parse a .config-format file
+pub fn read(path: &Path) -> Result<Vec<Assignment>, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    Ok(parse(&content))
+}
+
+/// Remove the surrounding quotes and .config escaping from a string value
+pub fn unquote_string(value: &str) -> Option<String> {
+    let inner = value.strip_prefix('"')?.strip_suffix('"')?;
+
+    let mut result = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(escaped) = chars.next() {
+                result.push(escaped);
+            }
+        } else {
+            result.push(c);
+        }
+    }
+
+    Some(result)
+}
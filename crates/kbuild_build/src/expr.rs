@@ -0,0 +1,348 @@
+// Kconfig expressions (`depends on NET && (PCI || USB=y)`)
+//
+// Expressions evaluate to a tristate with Kconfig's rules: `n < m < y`,
+// `&&` takes the minimum, `||` the maximum and `!` mirrors (`!m` is `m`).
+// A symbol that is not set, or is not a bool/tristate, is `n`. Comparisons
+// (`=`, `!=`, `<`, `<=`, `>`, `>=`) yield `y` or `n`; ints are compared
+// numerically, everything else as strings.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use super::symbols::parse_int;
+
+/// Kconfig tristate value, ordered `No < Module < Yes`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Tristate {
+    No,
+    Module,
+    Yes,
+}
+
+impl Tristate {
+    /// Tristate of a raw .config value (`y`/`m`; anything else is `n`)
+    pub fn from_raw(raw: Option<&str>) -> Tristate {
+        match raw {
+            Some("y") => Tristate::Yes,
+            Some("m") => Tristate::Module,
+            _ => Tristate::No,
+        }
+    }
+
+    fn not(self) -> Tristate {
+        match self {
+            Tristate::No => Tristate::Yes,
+            Tristate::Module => Tristate::Module,
+            Tristate::Yes => Tristate::No,
+        }
+    }
+
+    fn from_bool(value: bool) -> Tristate {
+        if value {
+            Tristate::Yes
+        } else {
+            Tristate::No
+        }
+    }
+}
+
+impl fmt::Display for Tristate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Tristate::No => "n",
+            Tristate::Module => "m",
+            Tristate::Yes => "y",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Symbol(String),
+    Literal(String),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+    Compare(&'static str),
+}
+
+fn tokenize(expr: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = expr.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            _ if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' | ')' => {
+                chars.next();
+                tokens.push(if c == '(' { Token::Open } else { Token::Close });
+            }
+            '&' | '|' => {
+                chars.next();
+                if chars.next() != Some(c) {
+                    return Err(format!("expected '{}{}' in '{}'", c, c, expr));
+                }
+                tokens.push(if c == '&' { Token::And } else { Token::Or });
+            }
+            '!' | '=' | '<' | '>' => {
+                chars.next();
+                let with_eq = chars.peek() == Some(&'=');
+                if with_eq {
+                    chars.next();
+                }
+                tokens.push(match (c, with_eq) {
+                    ('!', false) => Token::Not,
+                    ('!', true) => Token::Compare("!="),
+                    ('=', _) => Token::Compare("="),
+                    ('<', false) => Token::Compare("<"),
+                    ('<', true) => Token::Compare("<="),
+                    ('>', false) => Token::Compare(">"),
+                    _ => Token::Compare(">="),
+                });
+            }
+            '"' | '\'' => {
+                chars.next();
+                let mut literal = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\\') => literal.extend(chars.next()),
+                        Some(q) if q == c => break,
+                        Some(other) => literal.push(other),
+                        None => return Err(format!("unterminated string in '{}'", expr)),
+                    }
+                }
+                tokens.push(Token::Literal(literal));
+            }
+            _ if c.is_ascii_alphanumeric() || c == '_' || c == '-' => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                        word.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(Token::Symbol(word));
+            }
+            _ => return Err(format!("unexpected '{}' in '{}'", c, expr)),
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Recursive-descent evaluator over the token stream
+struct Evaluator<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    config: &'a BTreeMap<String, String>,
+}
+
+impl Evaluator<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Tristate, String> {
+        let mut value = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            value = value.max(self.and()?);
+        }
+        Ok(value)
+    }
+
+    fn and(&mut self) -> Result<Tristate, String> {
+        let mut value = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            value = value.min(self.unary()?);
+        }
+        Ok(value)
+    }
+
+    fn unary(&mut self) -> Result<Tristate, String> {
+        if self.peek() == Some(&Token::Not) {
+            self.next();
+            return Ok(self.unary()?.not());
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Tristate, String> {
+        let left = match self.next() {
+            Some(Token::Open) => {
+                let value = self.or()?;
+                if self.next() != Some(Token::Close) {
+                    return Err("missing ')'".to_string());
+                }
+                return Ok(value);
+            }
+            Some(Token::Symbol(name)) => Operand::Symbol(name),
+            Some(Token::Literal(literal)) => Operand::Literal(literal),
+            other => return Err(format!("expected a symbol, found {:?}", other)),
+        };
+
+        let op = match self.peek() {
+            Some(Token::Compare(op)) => *op,
+            _ => return Ok(left.tristate(self.config)),
+        };
+        self.next();
+
+        let right = match self.next() {
+            Some(Token::Symbol(name)) => Operand::Symbol(name),
+            Some(Token::Literal(literal)) => Operand::Literal(literal),
+            other => return Err(format!("expected a value after '{}', found {:?}", op, other)),
+        };
+
+        Ok(Tristate::from_bool(compare(
+            &left.value(self.config),
+            op,
+            &right.value(self.config),
+        )))
+    }
+}
+
+enum Operand {
+    Symbol(String),
+    Literal(String),
+}
+
+impl Operand {
+    /// Value in a boolean context
+    fn tristate(&self, config: &BTreeMap<String, String>) -> Tristate {
+        match self {
+            Operand::Symbol(name) => match name.as_str() {
+                "y" | "m" | "n" => Tristate::from_raw(Some(name)),
+                _ => Tristate::from_raw(config.get(name).map(|v| v.as_str())),
+            },
+            Operand::Literal(literal) => Tristate::from_raw(Some(literal)),
+        }
+    }
+
+    /// Value in a comparison: a symbol's value (`n` if unset) or the constant itself
+    fn value(&self, config: &BTreeMap<String, String>) -> String {
+        match self {
+            Operand::Symbol(name) => match config.get(name) {
+                Some(raw) => super::dotconfig::unquote_string(raw).unwrap_or_else(|| raw.clone()),
+                None if is_identifier(name) && !matches!(name.as_str(), "y" | "m" | "n") => "n".to_string(),
+                None => name.clone(),
+            },
+            Operand::Literal(literal) => literal.clone(),
+        }
+    }
+}
+
+/// Whether a word names a symbol rather than a numeric constant
+fn is_identifier(word: &str) -> bool {
+    parse_int(word).is_none()
+}
+
+fn compare(left: &str, op: &str, right: &str) -> bool {
+    let ordering = match (parse_int(left), parse_int(right)) {
+        (Some(l), Some(r)) => l.cmp(&r),
+        _ if is_tristate_value(left) && is_tristate_value(right) => {
+            Tristate::from_raw(Some(left)).cmp(&Tristate::from_raw(Some(right)))
+        }
+        _ => left.cmp(right),
+    };
+
+    match op {
+        "=" => ordering.is_eq(),
+        "!=" => !ordering.is_eq(),
+        "<" => ordering.is_lt(),
+        "<=" => ordering.is_le(),
+        ">" => ordering.is_gt(),
+        _ => ordering.is_ge(),
+    }
+}
+
+fn is_tristate_value(value: &str) -> bool {
+    matches!(value, "y" | "m" | "n")
+}
+
+/// Evaluate a Kconfig expression against the configuration
+pub fn evaluate(expr: &str, config: &BTreeMap<String, String>) -> Result<Tristate, String> {
+    let mut evaluator = Evaluator {
+        tokens: tokenize(expr)?,
+        pos: 0,
+        config,
+    };
+
+    let value = evaluator.or()?;
+    if evaluator.pos != evaluator.tokens.len() {
+        return Err(format!("unexpected trailing input in '{}'", expr));
+    }
+    Ok(value)
+}
+
+/// Symbols referenced by an expression, for error messages
+pub fn symbols(expr: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    for token in tokenize(expr).unwrap_or_default() {
+        if let Token::Symbol(name) = token {
+            if is_identifier(&name) && !is_tristate_value(&name) && !names.contains(&name) {
+                names.push(name);
+            }
+        }
+    }
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(entries: &[(&str, &str)]) -> BTreeMap<String, String> {
+        entries.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn evaluates_tristate_logic() {
+        let config = config(&[("NET", "y"), ("USB", "m")]);
+        assert_eq!(evaluate("NET", &config), Ok(Tristate::Yes));
+        assert_eq!(evaluate("NET && USB", &config), Ok(Tristate::Module));
+        assert_eq!(evaluate("PCI || USB", &config), Ok(Tristate::Module));
+        assert_eq!(evaluate("!USB", &config), Ok(Tristate::Module));
+        assert_eq!(evaluate("!PCI && (NET || PCI)", &config), Ok(Tristate::Yes));
+    }
+
+    #[test]
+    fn evaluates_comparisons() {
+        let config = config(&[("NR_CPUS", "8"), ("SCHED", "\"cfs\""), ("USB", "m")]);
+        assert_eq!(evaluate("NR_CPUS >= 4", &config), Ok(Tristate::Yes));
+        assert_eq!(evaluate("NR_CPUS < 10", &config), Ok(Tristate::Yes));
+        assert_eq!(evaluate("SCHED = \"cfs\"", &config), Ok(Tristate::Yes));
+        assert_eq!(evaluate("USB != y", &config), Ok(Tristate::Yes));
+        assert_eq!(evaluate("USB < y", &config), Ok(Tristate::Yes));
+        assert_eq!(evaluate("PCI = n", &config), Ok(Tristate::Yes));
+    }
+
+    #[test]
+    fn rejects_malformed_expressions() {
+        let config = BTreeMap::new();
+        assert!(evaluate("NET & PCI", &config).unwrap_err().contains("expected '&&'"));
+        assert!(evaluate("(NET", &config).unwrap_err().contains("missing ')'"));
+        assert!(evaluate("NET PCI", &config).unwrap_err().contains("trailing input"));
+        assert!(evaluate("NET = \"x", &config).unwrap_err().contains("unterminated string"));
+        assert!(evaluate("NET =", &config).unwrap_err().contains("expected a value"));
+        assert!(evaluate("NET && $", &config).unwrap_err().contains("unexpected '$'"));
+        assert!(evaluate("", &config).is_err());
+    }
+
+    #[test]
+    fn lists_referenced_symbols() {
+        assert_eq!(symbols("NET && (PCI || NET) && NR_CPUS > 4 && USB != y"), ["NET", "PCI", "NR_CPUS", "USB"]);
+    }
+}
@@ -0,0 +1,139 @@
+// Minimal reader for Kconfig symbol declarations
+//
+// cargo-kbuild does not evaluate Kconfig (that is the job of menuconfig and
+// friends); it only needs to know which symbols exist and their types, so
+// that check-cfg covers symbols that are absent from the current .config.
+//
+// Understood: `config`/`menuconfig NAME`, the type keywords (`bool`,
+// `tristate`, `int`, `hex`, `string`, `def_bool`, `def_tristate`), `range`,
+// `depends on` and `source`/`rsource`/`osource`. Everything else, including
+// help text and `if` blocks, is skipped.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::symbols::{parse_int, SymbolSpec, SymbolType};
+
+/// Read the symbols declared in `path` and the files it sources
+///
+/// `source` paths are resolved against `workspace_root` (Linux's srctree),
+/// `rsource` paths against the directory of the including file. Also
+/// returns every file read, for rebuild tracking.
+pub fn read_symbols(
+    workspace_root: &Path,
+    path: &Path,
+) -> Result<(BTreeMap<String, SymbolSpec>, Vec<PathBuf>), String> {
+    let mut symbols = BTreeMap::new();
+    let mut files = Vec::new();
+    read_file(workspace_root, path, &mut symbols, &mut files, 0)?;
+    Ok((symbols, files))
+}
+
+/// Nesting limit for `source`, which also catches include cycles
+const MAX_SOURCE_DEPTH: usize = 32;
+
+fn read_file(
+    workspace_root: &Path,
+    path: &Path,
+    symbols: &mut BTreeMap<String, SymbolSpec>,
+    files: &mut Vec<PathBuf>,
+    depth: usize,
+) -> Result<(), String> {
+    if depth > MAX_SOURCE_DEPTH {
+        return Err(format!("Kconfig sources nested too deeply at {}", path.display()));
+    }
+
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    files.push(path.to_path_buf());
+
+    let mut current: Option<String> = None;
+    let mut help_indent: Option<usize> = None;
+
+    for line in content.lines() {
+        let indent = line.len() - line.trim_start().len();
+        let line = line.trim();
+
+        // Help text runs until the first line indented less than its first line
+        if let Some(help) = help_indent {
+            if line.is_empty() || indent > help {
+                continue;
+            }
+            help_indent = None;
+        }
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (keyword, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+
+        match keyword {
+            "config" | "menuconfig" => {
+                symbols.entry(rest.to_string()).or_default();
+                current = Some(rest.to_string());
+            }
+            "help" | "---help---" => help_indent = Some(indent),
+            "source" | "rsource" | "osource" | "orsource" => {
+                let file = rest.trim_matches('"');
+                let base = if matches!(keyword, "rsource" | "orsource") {
+                    path.parent().unwrap_or(workspace_root)
+                } else {
+                    workspace_root
+                };
+                let sourced = base.join(file);
+                if !sourced.exists() && matches!(keyword, "osource" | "orsource") {
+                    continue;
+                }
+                read_file(workspace_root, &sourced, symbols, files, depth + 1)?;
+                current = None;
+            }
+            _ => {
+                let spec = match current.as_ref().and_then(|name| symbols.get_mut(name)) {
+                    Some(spec) => spec,
+                    None => continue,
+                };
+                if let Some(kind) = type_keyword(keyword) {
+                    spec.kind = Some(kind);
+                } else if keyword == "depends" {
+                    let expr = rest.strip_prefix("on").unwrap_or(rest).trim();
+                    spec.depends_on = Some(match spec.depends_on.take() {
+                        Some(existing) => format!("({}) && ({})", existing, expr),
+                        None => expr.to_string(),
+                    });
+                } else if keyword == "range" {
+                    let bounds: Vec<Option<i64>> = rest.split_whitespace().take(2).map(parse_int).collect();
+                    if let [Some(min), Some(max)] = bounds[..] {
+                        spec.range = Some([min, max]);
+                    }
+                } else if is_block_keyword(keyword) {
+                    current = None;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Symbol type introduced by a Kconfig keyword
+fn type_keyword(keyword: &str) -> Option<SymbolType> {
+    match keyword {
+        "bool" | "def_bool" => Some(SymbolType::Bool),
+        "tristate" | "def_tristate" => Some(SymbolType::Tristate),
+        "int" => Some(SymbolType::Int),
+        "hex" => Some(SymbolType::Hex),
+        "string" => Some(SymbolType::String),
+        _ => None,
+    }
+}
+
+/// Keywords that end the current config entry
+fn is_block_keyword(keyword: &str) -> bool {
+    matches!(
+        keyword,
+        "menu" | "endmenu" | "choice" | "endchoice" | "if" | "endif" | "comment" | "mainmenu"
+    )
+}
@@ -0,0 +1,329 @@
+// Build-script helper for kbuild-enabled crates
+//
+// A plain `cargo build` (or rust-analyzer) does not go through cargo-kbuild,
+// so the crate would be compiled without any kbuild cfgs. Calling
+// `kbuild_build::configure()` from build.rs applies the workspace .config
+// directly:
+//
+//   [build-dependencies]
+//   kbuild_build = { path = "../kbuild_build" }
+//
+//   // build.rs
+//   fn main() {
+//       kbuild_build::configure();
+//   }
+//
+// Under cargo-kbuild the same .config (or the one given with --kconfig) is
+// used, so both ways of building produce the same binary.
+//
+// Crates listing `symbols = [...]` in `[package.metadata.kbuild]` call
+// `kbuild_build::crate_module()` instead and include the constants from
+// `$OUT_DIR/kbuild_symbols.rs`.
+//
+// The parsing and rendering behind both (.config, Kconfig, symbol types,
+// generated constants) live in the modules below, which cargo-kbuild uses
+// as well, so the two always agree.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub mod dotconfig;
+pub mod expr;
+pub mod kconfig;
+pub mod render;
+pub mod symbols;
+pub mod workspace;
+
+use symbols::SymbolTable;
+use workspace::SymbolSources;
+
+/// Path of the .config to use; set by cargo-kbuild for every cargo run
+pub const CONFIG_ENV: &str = "KBUILD_CONFIG";
+/// Directory with cargo-kbuild's generated files
+pub const OUT_DIR_ENV: &str = "KBUILD_OUT_DIR";
+/// Set when cargo-kbuild runs cargo with its rustc wrapper
+pub const WRAPPER_ENV: &str = "KBUILD_RUSTC_WRAPPER";
+/// File name of the per-crate config module in `$OUT_DIR`
+pub const CRATE_MODULE: &str = "kbuild_symbols.rs";
+
+/// Display a path relative to the workspace root when possible
+pub fn display_path(workspace_root: &Path, path: &Path) -> String {
+    path.strip_prefix(workspace_root)
+        .unwrap_or(path)
+        .display()
+        .to_string()
+}
+
+/// `$CARGO_MANIFEST_DIR` of the crate whose build script is running
+fn manifest_dir() -> Result<PathBuf, String> {
+    env::var_os("CARGO_MANIFEST_DIR")
+        .map(PathBuf::from)
+        .ok_or_else(|| "CARGO_MANIFEST_DIR is not set; call kbuild_build from a build script".to_string())
+}
+
+/// The workspace's symbols as cargo-kbuild collects them, with the .config
+/// at `config_path` if there is one
+///
+/// Returns the workspace root along with the table.
+fn symbol_table(config_path: Option<&Path>) -> Result<(PathBuf, SymbolTable), String> {
+    let manifest_dir = manifest_dir()?;
+    let root = workspace::find_root(&manifest_dir).unwrap_or(manifest_dir);
+    let sources = SymbolSources::read(&root)?;
+    let table = SymbolTable::collect(
+        &root,
+        sources.kconfig_path(&root)?.as_deref(),
+        &sources.defconfig_paths(&root)?,
+        config_path,
+        &sources.symbols,
+    )?;
+    Ok((root, table))
+}
+
+/// Print `rerun-if-changed` for the files the symbols were read from
+fn watch_symbol_sources(root: &Path, table: &SymbolTable) {
+    println!("cargo:rerun-if-changed={}", root.join("Cargo.toml").display());
+    for file in table.files() {
+        println!("cargo:rerun-if-changed={}", file.display());
+    }
+}
+
+/// A parsed .config
+#[derive(Debug)]
+pub struct KbuildConfig {
+    path: PathBuf,
+    values: BTreeMap<String, String>,
+}
+
+impl KbuildConfig {
+    /// Locate the .config: `$KBUILD_CONFIG`, otherwise the first `.config`
+    /// found walking up from the crate's manifest directory
+    pub fn locate() -> Result<PathBuf, String> {
+        if let Some(path) = env::var_os(CONFIG_ENV) {
+            return Ok(PathBuf::from(path));
+        }
+
+        let manifest_dir = manifest_dir()?;
+        for dir in manifest_dir.ancestors() {
+            let candidate = dir.join(".config");
+            if candidate.is_file() {
+                return Ok(candidate);
+            }
+        }
+
+        Err(format!(
+            "No .config found above {} (set {} to its path)",
+            manifest_dir.display(),
+            CONFIG_ENV
+        ))
+    }
+
+    /// Locate and parse the .config
+    pub fn load() -> Result<KbuildConfig, String> {
+        KbuildConfig::from_path(&KbuildConfig::locate()?)
+    }
+
+    /// Parse the .config at `path`
+    pub fn from_path(path: &Path) -> Result<KbuildConfig, String> {
+        let values = dotconfig::read(path)?
+            .into_iter()
+            .filter_map(|a| Some((a.name, a.value?)))
+            .collect();
+
+        Ok(KbuildConfig {
+            path: path.to_path_buf(),
+            values,
+        })
+    }
+
+    /// Path of the parsed .config
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Raw value of a symbol as written in .config (`y`, `3`, `"cfs"`)
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(|v| v.as_str())
+    }
+
+    /// cfgs for the configuration, as cargo-kbuild passes them to rustc
+    ///
+    /// * `SMP=y` -> `SMP`
+    /// * `NET=m` -> `NET_MODULE`
+    /// * `LOG_LEVEL=3` -> `LOG_LEVEL="3"`
+    pub fn cfgs(&self) -> Vec<String> {
+        self.values
+            .iter()
+            .filter_map(|(name, raw)| symbols::cfg_flag(name, raw))
+            .collect()
+    }
+
+    /// check-cfg specs for the configuration
+    ///
+    /// The same declarations cargo-kbuild generates: every symbol of the
+    /// workspace Kconfig, defconfigs, `[workspace.metadata.kbuild.symbols]`
+    /// and this .config, typed from all of them.
+    pub fn check_cfgs(&self) -> Result<Vec<String>, String> {
+        let (_, table) = symbol_table(Some(&self.path))?;
+        table.check_cfgs(&self.values)
+    }
+
+    /// Write the crate's config module to `$OUT_DIR` and print the
+    /// `cargo:` instructions that keep it current
+    ///
+    /// Under cargo-kbuild (`$KBUILD_CONFIG` set) the module it generated in
+    /// target/kbuild/crates is copied and only that file is watched, so the
+    /// crate is rebuilt only when one of its own symbols changes. A plain
+    /// cargo build renders the module from .config instead.
+    pub fn emit_crate_module(&self) -> Result<(), String> {
+        let manifest_dir = manifest_dir()?;
+        let out_dir = PathBuf::from(env::var_os("OUT_DIR").ok_or("OUT_DIR is not set; call kbuild_build from a build script")?);
+        let crate_name = env::var("CARGO_PKG_NAME").unwrap_or_default();
+        let module_path = out_dir.join(CRATE_MODULE);
+
+        println!("cargo:rerun-if-env-changed={}", CONFIG_ENV);
+        println!("cargo:rerun-if-env-changed={}", OUT_DIR_ENV);
+
+        let kbuild_out_dir = match env::var_os(OUT_DIR_ENV) {
+            Some(dir) => Some(PathBuf::from(dir)),
+            None => self.path.parent().map(|dir| dir.join("target/kbuild")),
+        };
+        let generated = kbuild_out_dir
+            .map(|dir| dir.join("crates").join(format!("{}.rs", crate_name)))
+            .filter(|path| env::var_os(CONFIG_ENV).is_some() && path.is_file());
+        if let Some(generated) = generated {
+            fs::copy(&generated, &module_path)
+                .map_err(|e| format!("Failed to copy {}: {}", generated.display(), e))?;
+            println!("cargo:rerun-if-changed={}", generated.display());
+            return Ok(());
+        }
+
+        let manifest_path = manifest_dir.join("Cargo.toml");
+        let manifest = workspace::read_manifest(&manifest_path)?;
+        let symbols: Vec<String> = manifest
+            .get("package")
+            .and_then(|p| p.get("metadata"))
+            .and_then(|m| m.get("kbuild"))
+            .and_then(|k| k.get("symbols"))
+            .ok_or_else(|| format!("{} has no `symbols` in [package.metadata.kbuild]", manifest_path.display()))?
+            .clone()
+            .try_into()
+            .map_err(|e| format!("Invalid `symbols` in {}: {}", manifest_path.display(), e))?;
+
+        let (root, table) = symbol_table(Some(&self.path))?;
+        let header = format!(
+            "// Generated by kbuild_build for crate '{}' from {}\n// Only contains the symbols listed in [package.metadata.kbuild] symbols\n\n",
+            crate_name,
+            self.path.display()
+        );
+        let (content, missing) = render::crate_module(&header, &symbols, &self.values, &table)?;
+        for symbol in missing {
+            println!("cargo:warning=symbol '{}' is not set in {}", symbol, self.path.display());
+        }
+
+        fs::write(&module_path, content)
+            .map_err(|e| format!("Failed to write {}: {}", module_path.display(), e))?;
+        println!("cargo:rerun-if-changed={}", manifest_path.display());
+        watch_symbol_sources(&root, &table);
+        Ok(())
+    }
+
+    /// Warn about enabled symbols whose same-named feature is off
+    ///
+    /// A build script cannot enable features, and cargo-kbuild normally
+    /// passes them with `--features`. Without it, optional dependencies of
+    /// an enabled symbol are missing, so point that out.
+    fn warn_disabled_features(&self) -> Result<(), String> {
+        let manifest = workspace::read_manifest(&manifest_dir()?.join("Cargo.toml"))?;
+        let features = manifest.get("features").and_then(|f| f.as_table());
+
+        for feature in features.into_iter().flat_map(|f| f.keys()) {
+            let enabled = matches!(self.get(feature), Some("y") | Some("m"));
+            let env_name = format!("CARGO_FEATURE_{}", feature.to_uppercase().replace('-', "_"));
+            if enabled && env::var_os(env_name).is_none() {
+                println!(
+                    "cargo:warning={}={} in {} but feature `{}` is not enabled; build with `cargo kbuild` or `--features {}`",
+                    feature,
+                    self.get(feature).unwrap_or_default(),
+                    self.path.display(),
+                    feature,
+                    feature
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Print the `cargo:` instructions applying the configuration
+    ///
+    /// Under cargo-kbuild's rustc wrapper nothing is emitted: the wrapper
+    /// already passes each crate the cfgs of the symbols it uses, and
+    /// rerunning on every .config change would rebuild the crate each time.
+    pub fn emit(&self) -> Result<(), String> {
+        println!("cargo:rerun-if-env-changed={}", WRAPPER_ENV);
+        if env::var_os(WRAPPER_ENV).is_some() {
+            return Ok(());
+        }
+        println!("cargo:rerun-if-env-changed={}", CONFIG_ENV);
+
+        let (root, table) = symbol_table(Some(&self.path))?;
+        for check_cfg in table.check_cfgs(&self.values)? {
+            println!("cargo:rustc-check-cfg={}", check_cfg);
+        }
+        for cfg in self.cfgs() {
+            println!("cargo:rustc-cfg={}", cfg);
+        }
+
+        self.warn_disabled_features()?;
+        watch_symbol_sources(&root, &table);
+        Ok(())
+    }
+}
+
+/// Declare the workspace's symbols to check-cfg without setting any cfg
+///
+/// Used when there is no .config, so `#[cfg(SMP)]` code still compiles
+/// (disabled) without "unexpected cfg" warnings.
+fn emit_check_cfgs() -> Result<(), String> {
+    println!("cargo:rerun-if-env-changed={}", WRAPPER_ENV);
+    println!("cargo:rerun-if-env-changed={}", CONFIG_ENV);
+
+    let (root, table) = symbol_table(None)?;
+    for check_cfg in table.check_cfgs(&BTreeMap::new())? {
+        println!("cargo:rustc-check-cfg={}", check_cfg);
+    }
+    watch_symbol_sources(&root, &table);
+    Ok(())
+}
+
+/// Apply the workspace .config to the crate being built
+///
+/// Call from build.rs. Without a .config the crate is built with every
+/// symbol disabled: only the check-cfg declarations are emitted, along
+/// with a warning. An invalid configuration panics, failing the build
+/// script like any other configuration error.
+pub fn configure() {
+    let result = match KbuildConfig::locate() {
+        Ok(path) => KbuildConfig::from_path(&path).and_then(|config| config.emit()),
+        Err(e) => {
+            println!("cargo:warning={}; building without kbuild cfgs", e);
+            emit_check_cfgs()
+        }
+    };
+    if let Err(e) = result {
+        panic!("kbuild_build: {}", e);
+    }
+}
+
+/// Generate the crate's config module in `$OUT_DIR`
+///
+/// Call from build.rs of a crate listing `symbols = [...]`, then include it:
+/// `include!(concat!(env!("OUT_DIR"), "/kbuild_symbols.rs"))`. Unlike
+/// `configure()` this panics without a .config, as the constants would
+/// have no values.
+pub fn crate_module() {
+    if let Err(e) = KbuildConfig::load().and_then(|config| config.emit_crate_module()) {
+        panic!("kbuild_build: {}", e);
+    }
+}
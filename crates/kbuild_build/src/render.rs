@@ -0,0 +1,107 @@
+// Rust constants for .config values
+//
+// Shared by cargo-kbuild (config.rs, target/kbuild/crates/<crate>.rs) and
+// the build-script helper, so a module rendered in a plain `cargo build`
+// matches the one cargo-kbuild generates.
+
+use std::collections::BTreeMap;
+
+use super::symbols::{SymbolTable, SymbolType};
+
+/// Definition of the `Tristate` type used by tristate constants
+pub const TRISTATE_DEFINITION: &str = r#"/// Value of a tristate symbol: built in, built as a module, or disabled
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Tristate {
+    No,
+    Module,
+    Yes,
+}
+
+#[allow(dead_code)]
+impl Tristate {
+    /// `IS_BUILTIN()`: the symbol is `y`
+    pub const fn is_builtin(self) -> bool {
+        matches!(self, Tristate::Yes)
+    }
+
+    /// `IS_MODULE()`: the symbol is `m`
+    pub const fn is_module(self) -> bool {
+        matches!(self, Tristate::Module)
+    }
+
+    /// `IS_ENABLED()`: the symbol is `y` or `m`
+    pub const fn is_enabled(self) -> bool {
+        !matches!(self, Tristate::No)
+    }
+}
+
+"#;
+
+/// Render the Rust constant for a single config value
+///
+/// Tristate symbols become `Tristate` constants. Returns `None` for bool
+/// configs (y/n), which are handled via --cfg.
+pub fn render_constant(key: &str, value: &str, kind: Option<SymbolType>) -> Option<String> {
+    if kind == Some(SymbolType::Tristate) {
+        let variant = match value {
+            "y" => "Yes",
+            "m" => "Module",
+            _ => "No",
+        };
+        return Some(format!("#[allow(dead_code)]\npub const {}: Tristate = Tristate::{};\n\n", key, variant));
+    }
+
+    if value == "y" || value == "n" || value == "m" {
+        return None;
+    }
+
+    // Try to parse as integer
+    if let Ok(int_val) = value.parse::<i32>() {
+        Some(format!("#[allow(dead_code)]\npub const {}: i32 = {};\n\n", key, int_val))
+    }
+    // Check if it's a string (starts and ends with quotes)
+    else if value.starts_with('"') && value.ends_with('"') {
+        let str_val = &value[1..value.len()-1]; // Remove quotes
+        Some(format!("#[allow(dead_code)]\npub const {}: &str = \"{}\";\n\n", key, str_val))
+    }
+    // Otherwise treat as usize
+    else if let Ok(uint_val) = value.parse::<usize>() {
+        Some(format!("#[allow(dead_code)]\npub const {}: usize = {};\n\n", key, uint_val))
+    } else {
+        None
+    }
+}
+
+/// Render a per-crate config module: `header`, then the constants of `symbols`
+///
+/// The crate does not depend on kbuild_config, so the module carries its own
+/// `Tristate` definition when needed. Also returns the symbols that are not
+/// set in `config`, for the caller to report.
+pub fn crate_module<'a>(
+    header: &str,
+    symbols: &'a [String],
+    config: &BTreeMap<String, String>,
+    symbol_table: &SymbolTable,
+) -> Result<(String, Vec<&'a str>), String> {
+    let mut constants = String::new();
+    let mut missing = Vec::new();
+    for symbol in symbols {
+        match config.get(symbol) {
+            Some(value) => {
+                let kind = symbol_table.symbol_type(symbol, config)?;
+                if let Some(constant) = render_constant(symbol, value, kind) {
+                    constants.push_str(&constant);
+                }
+            }
+            None => missing.push(symbol.as_str()),
+        }
+    }
+
+    let mut content = header.to_string();
+    if constants.contains(": Tristate = ") {
+        content.push_str(TRISTATE_DEFINITION);
+    }
+    content.push_str(&constants);
+    Ok((content, missing))
+}
@@ -0,0 +1,521 @@
+// cfg and check-cfg flags for .config symbols
+//
+// Bool symbols become name-only cfgs (`#[cfg(SMP)]`). Int, hex and string
+// symbols become key-value cfgs (`#[cfg(DEFAULT_SCHEDULER = "cfs")]`), whose
+// accepted values are declared in the workspace manifest:
+//
+//   [workspace.metadata.kbuild.symbols]
+//   DEFAULT_SCHEDULER = { choices = ["cfs", "rr", "fifo"] }
+//   LOG_LEVEL = { range = [0, 7] }
+//
+// rustc then warns about `#[cfg(DEFAULT_SCHEDULER = "cfz")]`. check-cfg is
+// type-aware: bool symbols are declared name-only and non-bool symbols
+// key-value only, so `#[cfg(LOG_LEVEL)]` (always false) warns as well.
+//
+// Tristate symbols set to `m` become `NAME_MODULE` instead of `NAME`, like
+// CONFIG_X_MODULE in Linux, so built-in and modular builds can be told apart.
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Ranges with more values than this are declared as `values(any())`
+const MAX_RANGE_VALUES: i64 = 1024;
+
+/// Kconfig symbol types
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SymbolType {
+    Bool,
+    Tristate,
+    Int,
+    Hex,
+    String,
+}
+
+impl SymbolType {
+    /// Whether the symbol is used as a name-only cfg (`#[cfg(SMP)]`)
+    pub fn is_bool(self) -> bool {
+        matches!(self, SymbolType::Bool | SymbolType::Tristate)
+    }
+}
+
+/// Declaration of a symbol in `[workspace.metadata.kbuild.symbols]`
+#[derive(Debug, Deserialize, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SymbolSpec {
+    /// Symbol type; inferred from `choices`/`range` or the .config value if omitted
+    #[serde(rename = "type")]
+    pub kind: Option<SymbolType>,
+    /// Allowed values of a string symbol
+    pub choices: Option<Vec<String>>,
+    /// Inclusive `[min, max]` bounds of an int symbol
+    pub range: Option<[i64; 2]>,
+    /// Kconfig expression limiting the symbol's value (`depends on`)
+    #[serde(rename = "depends-on")]
+    pub depends_on: Option<String>,
+}
+
+impl SymbolSpec {
+    /// Apply the fields set in `other` on top of this declaration
+    fn overlay(&mut self, other: &SymbolSpec) {
+        if other.kind.is_some() {
+            self.kind = other.kind;
+        }
+        if other.choices.is_some() {
+            self.choices = other.choices.clone();
+        }
+        if other.range.is_some() {
+            self.range = other.range;
+        }
+        if other.depends_on.is_some() {
+            self.depends_on = other.depends_on.clone();
+        }
+    }
+
+    /// Values accepted by `#[cfg(NAME = "...")]`, or `None` for any value
+    fn values(&self, name: &str) -> Result<Option<Vec<String>>, String> {
+        match (&self.choices, &self.range) {
+            (Some(_), Some(_)) => Err(format!(
+                "Symbol {} declares both `choices` and `range`",
+                name
+            )),
+            (Some(choices), None) => Ok(Some(choices.clone())),
+            // Hex values are spelled freely (0x10, 0X0010), so any is accepted
+            (None, Some(_)) if self.kind == Some(SymbolType::Hex) => Ok(None),
+            (None, Some([min, max])) => {
+                if min > max {
+                    return Err(format!("Symbol {} has an empty range [{}, {}]", name, min, max));
+                }
+                if max - min >= MAX_RANGE_VALUES {
+                    return Ok(None);
+                }
+                Ok(Some((*min..=*max).map(|v| v.to_string()).collect()))
+            }
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// Type of the symbol from its declaration and current .config value
+    fn resolve_type(&self, name: &str, raw: Option<&str>) -> Result<SymbolType, String> {
+        let declared = match (self.kind, &self.choices, &self.range) {
+            (Some(kind), _, _) => Some(kind),
+            (None, Some(_), _) => Some(SymbolType::String),
+            (None, None, Some(_)) => Some(SymbolType::Int),
+            (None, None, None) => None,
+        };
+
+        match (declared, raw) {
+            (Some(SymbolType::Bool), Some("m")) => Err(format!(
+                "{}=m but {} is a bool symbol; only tristate symbols can be modules",
+                name, name
+            )),
+            (Some(kind), Some(raw)) if kind.is_bool() != is_bool_value(raw) => Err(format!(
+                "{}={} does not match its declared type {:?}",
+                name,
+                raw,
+                kind
+            )),
+            (Some(kind), _) => Ok(kind),
+            (None, Some(raw)) => Ok(value_type(raw)),
+            (None, None) => Ok(SymbolType::Bool),
+        }
+    }
+
+    /// Check that the configured value is one the declaration allows
+    fn validate(&self, name: &str, value: &str) -> Result<(), String> {
+        if let Some(choices) = &self.choices {
+            if !choices.iter().any(|c| c == value) {
+                return Err(format!(
+                    "{}=\"{}\" is not one of the declared choices: {}",
+                    name,
+                    value,
+                    choices.join(", ")
+                ));
+            }
+        }
+        if let Some([min, max]) = self.range {
+            let in_range = parse_int(value).map(|v| v >= min && v <= max).unwrap_or(false);
+            if !in_range {
+                return Err(format!(
+                    "{}={} is outside the declared range [{}, {}]",
+                    name, value, min, max
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether a raw .config value is a bool/tristate (`y`, `n`, `m`)
+pub fn is_bool_value(raw: &str) -> bool {
+    matches!(raw, "y" | "n" | "m")
+}
+
+/// Parse a decimal or `0x` hex number as written in Kconfig and .config
+pub fn parse_int(value: &str) -> Option<i64> {
+    match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(hex) => i64::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+/// Type implied by a raw .config value
+fn value_type(raw: &str) -> SymbolType {
+    match raw {
+        "y" | "n" => SymbolType::Bool,
+        "m" => SymbolType::Tristate,
+        _ if raw.starts_with('"') => SymbolType::String,
+        _ if raw.parse::<i64>().is_ok() => SymbolType::Int,
+        _ if raw.starts_with("0x") || raw.starts_with("0X") => SymbolType::Hex,
+        _ => SymbolType::String,
+    }
+}
+
+/// Value of a non-bool symbol as compared in `#[cfg(NAME = "value")]`
+fn cfg_value(raw: &str) -> String {
+    super::dotconfig::unquote_string(raw).unwrap_or_else(|| raw.to_string())
+}
+
+/// cfg set for a tristate symbol built as a module
+pub fn module_cfg(name: &str) -> String {
+    format!("{}_MODULE", name)
+}
+
+/// Render a Rust string literal for a cfg value
+fn literal(value: &str) -> String {
+    format!("{:?}", value)
+}
+
+/// The `--cfg` argument for a symbol, or `None` if it is disabled
+///
+/// * `SMP=y` -> `SMP`
+/// * `NET=m` -> `NET_MODULE`
+/// * `LOG_LEVEL=3` -> `LOG_LEVEL="3"`
+/// * `DEFAULT_SCHEDULER="cfs"` -> `DEFAULT_SCHEDULER="cfs"`
+pub fn cfg_flag(name: &str, raw: &str) -> Option<String> {
+    match raw {
+        "y" => Some(name.to_string()),
+        "m" => Some(module_cfg(name)),
+        "n" => None,
+        _ => Some(format!("{}={}", name, literal(&cfg_value(raw)))),
+    }
+}
+
+/// Every symbol that may appear in a configuration
+///
+/// check-cfg is generated from this table rather than from the current
+/// .config, so removing `DEBUG=n` does not turn `#[cfg(DEBUG)]` into an
+/// "unexpected cfg" warning, while a misspelled symbol still gets one.
+#[derive(Debug, Default)]
+pub struct SymbolTable {
+    /// Declaration per symbol (default for undeclared ones)
+    specs: BTreeMap<String, SymbolSpec>,
+    /// A value seen for the symbol in a defconfig, used to infer its type
+    samples: BTreeMap<String, String>,
+    /// Number of symbols contributed by each source, for the build summary
+    counts: Vec<(String, usize)>,
+    /// Every file the symbols were read from
+    files: Vec<PathBuf>,
+}
+
+impl SymbolTable {
+    /// Collect the union of:
+    ///
+    /// * `config`/`menuconfig` entries of the Kconfig file (and its sources)
+    /// * assignments and `# X is not set` lines of every defconfig
+    /// * the current .config (if any), including its `# X is not set` lines
+    /// * `[workspace.metadata.kbuild.symbols]`, which overrides Kconfig types
+    pub fn collect(
+        workspace_root: &Path,
+        kconfig: Option<&Path>,
+        defconfigs: &[PathBuf],
+        config_path: Option<&Path>,
+        metadata: &BTreeMap<String, SymbolSpec>,
+    ) -> Result<SymbolTable, String> {
+        let mut table = SymbolTable::default();
+
+        if let Some(kconfig) = kconfig {
+            let (declared, files) = super::kconfig::read_symbols(workspace_root, kconfig)?;
+            table.counts.push((super::display_path(workspace_root, kconfig), declared.len()));
+            table.specs.extend(declared);
+            table.files.extend(files);
+        }
+
+        for defconfig in defconfigs {
+            let values = read_symbol_file(defconfig)?;
+            table.counts.push((super::display_path(workspace_root, defconfig), values.len()));
+            table.add_samples(values);
+            table.files.push(defconfig.clone());
+        }
+
+        if let Some(config_path) = config_path {
+            let values = read_symbol_file(config_path)?;
+            table.counts.push((super::display_path(workspace_root, config_path), values.len()));
+            table.add_samples(values);
+            table.files.push(config_path.to_path_buf());
+        }
+
+        table.counts.push(("workspace metadata".to_string(), metadata.len()));
+        for (name, spec) in metadata {
+            table.specs.entry(name.clone()).or_default().overlay(spec);
+        }
+
+        Ok(table)
+    }
+
+    fn add_samples(&mut self, values: BTreeMap<String, String>) {
+        for (name, value) in values {
+            self.specs.entry(name.clone()).or_default();
+            // An `m` anywhere marks the symbol as tristate
+            if value == "m" {
+                self.samples.insert(name, value);
+            } else {
+                self.samples.entry(name).or_insert(value);
+            }
+        }
+    }
+
+    /// Type of a symbol, or `None` if it is unknown
+    pub fn symbol_type(&self, name: &str, config: &BTreeMap<String, String>) -> Result<Option<SymbolType>, String> {
+        match self.specs.get(name) {
+            Some(spec) => spec.resolve_type(name, self.sample(name, config)).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// The current value of a symbol, or a value from a defconfig
+    fn sample<'a>(&'a self, name: &str, config: &'a BTreeMap<String, String>) -> Option<&'a str> {
+        match (config.get(name).map(|raw| raw.as_str()), self.samples.get(name)) {
+            (Some(raw), Some(sample)) if raw != "m" && sample == "m" => Some("m"),
+            (Some(raw), _) => Some(raw),
+            (None, sample) => sample.map(|raw| raw.as_str()),
+        }
+    }
+
+    /// Check every enabled symbol against its `depends on` expression
+    ///
+    /// As in Kconfig, a tristate symbol cannot be more enabled than its
+    /// dependencies: `y` needs them to be `y`, while a dependency that is `m`
+    /// only allows `m`. Bool symbols only need the dependencies to be enabled.
+    pub fn check_dependencies(&self, config: &BTreeMap<String, String>) -> Result<(), String> {
+        let mut errors = Vec::new();
+
+        for (name, spec) in &self.specs {
+            let depends_on = match &spec.depends_on {
+                Some(depends_on) => depends_on,
+                None => continue,
+            };
+            let value = super::expr::Tristate::from_raw(config.get(name).map(|raw| raw.as_str()));
+            if value == super::expr::Tristate::No {
+                continue;
+            }
+
+            let limit = super::expr::evaluate(depends_on, config)
+                .map_err(|e| format!("Invalid dependency of {}: {}", name, e))?;
+            let is_tristate = self.symbol_type(name, config)? == Some(SymbolType::Tristate);
+            let allowed = if is_tristate || limit == super::expr::Tristate::No {
+                value <= limit
+            } else {
+                true
+            };
+
+            if !allowed {
+                let deps: Vec<String> = super::expr::symbols(depends_on)
+                    .into_iter()
+                    .map(|dep| format!("{}={}", dep, config.get(&dep).map(|v| v.as_str()).unwrap_or("n")))
+                    .collect();
+                errors.push(format!(
+                    "{}={} but it depends on `{}` ({}), which only allows {}",
+                    name,
+                    value,
+                    depends_on,
+                    deps.join(", "),
+                    limit
+                ));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("Unmet dependencies in .config:\n  - {}", errors.join("\n  - ")))
+        }
+    }
+
+    /// Check that `value` is a value the symbol can take
+    ///
+    /// Used for values written outside .config, so only int, hex and string
+    /// symbols qualify; bool and tristate values are tested with expressions.
+    pub fn check_value(&self, name: &str, value: &str, config: &BTreeMap<String, String>) -> Result<(), String> {
+        let spec = self.specs.get(name).ok_or_else(|| format!("unknown symbol '{}'", name))?;
+        match spec.resolve_type(name, self.sample(name, config))? {
+            SymbolType::Bool | SymbolType::Tristate => Err(format!(
+                "{} is a bool/tristate symbol; use an expression such as \"{}\" instead",
+                name, name
+            )),
+            SymbolType::Int | SymbolType::Hex if parse_int(value).is_none() => {
+                Err(format!("'{}' is not a number, but {} is a numeric symbol", value, name))
+            }
+            _ => spec.validate(name, value),
+        }
+    }
+
+    /// Whether a symbol is known from any source
+    pub fn contains(&self, name: &str) -> bool {
+        self.specs.contains_key(name)
+    }
+
+    /// Number of distinct symbols
+    pub fn len(&self) -> usize {
+        self.specs.len()
+    }
+
+    /// Whether no symbol is known from any source
+    pub fn is_empty(&self) -> bool {
+        self.specs.is_empty()
+    }
+
+    /// The Kconfig files, defconfigs and .config the symbols were read from
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// `source (count)` list for the build summary
+    pub fn describe_sources(&self) -> String {
+        let sources: Vec<String> = self
+            .counts
+            .iter()
+            .filter(|(_, count)| *count > 0)
+            .map(|(source, count)| format!("{} ({})", source, count))
+            .collect();
+        sources.join(", ")
+    }
+
+    /// check-cfg specs (the part inside `--check-cfg=`) for all symbols
+    ///
+    /// * bool -> `cfg(SMP)`
+    /// * tristate -> `cfg(NET)` and `cfg(NET_MODULE)`
+    /// * int/hex/string -> `cfg(LOG_LEVEL,values(...))` with the declared
+    ///   choices or range, otherwise `values(any())`
+    ///
+    /// Values in the current `config` are validated against the declarations.
+    pub fn check_cfgs(&self, config: &BTreeMap<String, String>) -> Result<Vec<String>, String> {
+        Ok(self.check_cfgs_by_symbol(config)?.into_values().flatten().collect())
+    }
+
+    /// check-cfg specs grouped by symbol name, see `check_cfgs`
+    pub fn check_cfgs_by_symbol(
+        &self,
+        config: &BTreeMap<String, String>,
+    ) -> Result<BTreeMap<String, Vec<String>>, String> {
+        let mut check_cfgs = BTreeMap::new();
+
+        for (name, spec) in &self.specs {
+            let current = config.get(name).map(|raw| raw.as_str());
+
+            let specs = match spec.resolve_type(name, self.sample(name, config))? {
+                SymbolType::Bool => vec![format!("cfg({})", name)],
+                SymbolType::Tristate => vec![
+                    format!("cfg({})", name),
+                    format!("cfg({})", module_cfg(name)),
+                ],
+                _ => {
+                    if let Some(raw) = current {
+                        spec.validate(name, &cfg_value(raw))?;
+                    }
+
+                    let values = match spec.values(name)? {
+                        Some(values) => values.iter().map(|v| literal(v)).collect::<Vec<_>>().join(","),
+                        None => "any()".to_string(),
+                    };
+                    vec![format!("cfg({},values({}))", name, values)]
+                }
+            };
+            check_cfgs.insert(name.clone(), specs);
+        }
+
+        Ok(check_cfgs)
+    }
+}
+
+/// Read the symbols named in a .config-format file
+///
+/// `# X is not set` lines count as `X=n`, as written by Kconfig tools.
+fn read_symbol_file(path: &Path) -> Result<BTreeMap<String, String>, String> {
+    Ok(super::dotconfig::read(path)?
+        .into_iter()
+        .map(|a| (a.name, a.value.unwrap_or_else(|| "n".to_string())))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(metadata: &str) -> SymbolTable {
+        let metadata: BTreeMap<String, SymbolSpec> = toml::from_str(metadata).unwrap();
+        SymbolTable::collect(Path::new("."), None, &[], None, &metadata).unwrap()
+    }
+
+    fn config(entries: &[(&str, &str)]) -> BTreeMap<String, String> {
+        entries.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn check_value_accepts_declared_values() {
+        let table = table(
+            r#"
+            DEFAULT_SCHEDULER = { choices = ["cfs", "rr"] }
+            LOG_LEVEL = { range = [0, 7] }
+            BASE = { type = "hex" }
+            "#,
+        );
+        let config = BTreeMap::new();
+        assert_eq!(table.check_value("DEFAULT_SCHEDULER", "rr", &config), Ok(()));
+        assert_eq!(table.check_value("LOG_LEVEL", "7", &config), Ok(()));
+        assert_eq!(table.check_value("BASE", "0x1000", &config), Ok(()));
+    }
+
+    #[test]
+    fn check_value_rejects_unknown_symbols() {
+        let err = table("").check_value("MISSING", "1", &BTreeMap::new()).unwrap_err();
+        assert_eq!(err, "unknown symbol 'MISSING'");
+    }
+
+    #[test]
+    fn check_value_rejects_bool_symbols() {
+        let table = table(r#"SMP = { type = "bool" }"#);
+        let err = table.check_value("SMP", "y", &config(&[("SMP", "y")])).unwrap_err();
+        assert!(err.contains("bool/tristate"), "{}", err);
+    }
+
+    #[test]
+    fn check_value_rejects_non_numbers_for_numeric_symbols() {
+        let table = table("LOG_LEVEL = { range = [0, 7] }");
+        let err = table.check_value("LOG_LEVEL", "high", &BTreeMap::new()).unwrap_err();
+        assert!(err.contains("not a number"), "{}", err);
+    }
+
+    #[test]
+    fn check_value_rejects_undeclared_values() {
+        let table = table(
+            r#"
+            DEFAULT_SCHEDULER = { choices = ["cfs", "rr"] }
+            LOG_LEVEL = { range = [0, 7] }
+            "#,
+        );
+        let config = BTreeMap::new();
+        let err = table.check_value("DEFAULT_SCHEDULER", "fifo", &config).unwrap_err();
+        assert!(err.contains("declared choices: cfs, rr"), "{}", err);
+        let err = table.check_value("LOG_LEVEL", "8", &config).unwrap_err();
+        assert!(err.contains("outside the declared range [0, 7]"), "{}", err);
+    }
+
+    #[test]
+    fn check_value_infers_types_from_config() {
+        let table = table("NR_CPUS = {}");
+        assert_eq!(table.check_value("NR_CPUS", "4", &config(&[("NR_CPUS", "8")])), Ok(()));
+        assert!(table.check_value("NR_CPUS", "y", &config(&[("NR_CPUS", "y")])).is_err());
+    }
+}
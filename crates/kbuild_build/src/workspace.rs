@@ -0,0 +1,104 @@
+// Where the workspace declares its symbols
+//
+// `[workspace.metadata.kbuild]` names the Kconfig file and defconfigs and
+// declares the values of non-bool symbols. cargo-kbuild reads these settings
+// as part of its workspace metadata; the build-script helper reads them from
+// the workspace Cargo.toml, which it finds above the crate being built.
+
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::symbols::SymbolSpec;
+
+/// The symbol sources of `[workspace.metadata.kbuild]`
+#[derive(Debug, Deserialize, Default)]
+pub struct SymbolSources {
+    /// Accepted values of non-bool symbols, keyed by symbol name
+    #[serde(default)]
+    pub symbols: BTreeMap<String, SymbolSpec>,
+    /// Kconfig file declaring the symbols (default: `Kconfig` if present)
+    #[serde(default)]
+    pub kconfig: Option<String>,
+    /// defconfig files or directories of them (default: `configs/` if present)
+    #[serde(default)]
+    pub defconfigs: Option<Vec<String>>,
+}
+
+impl SymbolSources {
+    /// Read the settings from the workspace Cargo.toml in `workspace_root`
+    pub fn read(workspace_root: &Path) -> Result<SymbolSources, String> {
+        let path = workspace_root.join("Cargo.toml");
+        let manifest = read_manifest(&path)?;
+        match manifest.get("workspace").and_then(|w| w.get("metadata")).and_then(|m| m.get("kbuild")) {
+            Some(kbuild) => kbuild.clone().try_into()
+                .map_err(|e| format!("Invalid [workspace.metadata.kbuild] in {}: {}", path.display(), e)),
+            None => Ok(SymbolSources::default()),
+        }
+    }
+
+    /// Resolve the Kconfig file, falling back to `Kconfig` in the workspace root
+    pub fn kconfig_path(&self, workspace_root: &Path) -> Result<Option<PathBuf>, String> {
+        match &self.kconfig {
+            Some(path) => {
+                let path = workspace_root.join(path);
+                if !path.is_file() {
+                    return Err(format!("Kconfig file not found: {}", path.display()));
+                }
+                Ok(Some(path))
+            }
+            None => Ok(Some(workspace_root.join("Kconfig")).filter(|p| p.is_file())),
+        }
+    }
+
+    /// Resolve the defconfig files, expanding directories (sorted)
+    pub fn defconfig_paths(&self, workspace_root: &Path) -> Result<Vec<PathBuf>, String> {
+        let entries = match &self.defconfigs {
+            Some(entries) => entries.iter().map(|e| workspace_root.join(e)).collect(),
+            None => vec![workspace_root.join("configs")]
+                .into_iter()
+                .filter(|p| p.is_dir())
+                .collect::<Vec<_>>(),
+        };
+
+        let mut files = BTreeSet::new();
+        for entry in entries {
+            if entry.is_dir() {
+                let dir = fs::read_dir(&entry)
+                    .map_err(|e| format!("Failed to read {}: {}", entry.display(), e))?;
+                for file in dir {
+                    let file = file.map_err(|e| format!("Failed to read {}: {}", entry.display(), e))?;
+                    if file.path().is_file() {
+                        files.insert(file.path());
+                    }
+                }
+            } else if entry.is_file() {
+                files.insert(entry);
+            } else {
+                return Err(format!("defconfig not found: {}", entry.display()));
+            }
+        }
+
+        Ok(files.into_iter().collect())
+    }
+}
+
+/// Parse a Cargo.toml
+pub fn read_manifest(path: &Path) -> Result<toml::Value, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    toml::from_str(&content).map_err(|e| format!("Failed to parse {}: {}", path.display(), e))
+}
+
+/// The nearest directory at or above `dir` whose Cargo.toml has a
+/// `[workspace]` table
+pub fn find_root(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .find(|dir| {
+            read_manifest(&dir.join("Cargo.toml"))
+                .map(|manifest| manifest.get("workspace").is_some())
+                .unwrap_or(false)
+        })
+        .map(Path::to_path_buf)
+}
@@ -1,31 +1,56 @@
 use std::env;
 use std::fs;
-use std::path::Path;
+use std::path::PathBuf;
 
 fn main() {
+    // Apply the .config's cfgs the same way kbuild-enabled crates do, so
+    // plain `cargo build` matches `cargo kbuild build`
+    kbuild_build::configure();
+
     // Get the path to the generated config.rs
     let out_dir = env::var("OUT_DIR").unwrap();
-    let config_rs_path = Path::new(&out_dir).join("config.rs");
-    
+    let config_rs_path = PathBuf::from(&out_dir).join("config.rs");
+
+    // Generated files live in $KBUILD_OUT_DIR, or target/kbuild next to .config
+    let config_path = kbuild_build::KbuildConfig::locate().ok().filter(|path| path.is_file());
+    let kbuild_dir = env::var_os(kbuild_build::OUT_DIR_ENV)
+        .map(PathBuf::from)
+        .or_else(|| Some(config_path.as_ref()?.parent()?.join("target/kbuild")));
+    println!("cargo:rerun-if-env-changed={}", kbuild_build::OUT_DIR_ENV);
+
     // If cargo-kbuild has generated the config.rs, copy it to OUT_DIR
     // Otherwise, generate an empty one
-    let workspace_root = env::var("CARGO_MANIFEST_DIR").unwrap();
-    let workspace_root = Path::new(&workspace_root).parent().unwrap().parent().unwrap();
-    let target_config_path = workspace_root.join("target/kbuild/config.rs");
-    
-    if target_config_path.exists() {
+    let target_config_path = kbuild_dir.as_ref().map(|dir| dir.join("config.rs")).filter(|path| path.is_file());
+
+    if let Some(target_config_path) = &target_config_path {
         // Copy the generated config
-        let config_content = fs::read_to_string(&target_config_path)
+        let config_content = fs::read_to_string(target_config_path)
             .expect("Failed to read generated config.rs");
         fs::write(&config_rs_path, config_content)
             .expect("Failed to write config.rs to OUT_DIR");
+        println!("cargo:rerun-if-changed={}", target_config_path.display());
     } else {
         // Generate empty config if not available
         fs::write(&config_rs_path, "// No config.rs generated yet\n")
             .expect("Failed to write empty config.rs");
+        // Watching a missing file would rerun this script on every build;
+        // cargo-kbuild rereads .config before generating config.rs
+        if let Some(config_path) = &config_path {
+            println!("cargo:rerun-if-changed={}", config_path.display());
+        }
     }
-    
+
     // Set environment variable for inclusion
     println!("cargo:rustc-env=CONFIG_RS_PATH={}", config_rs_path.display());
-    println!("cargo:rerun-if-changed={}", target_config_path.display());
+
+    // Tell dependents' build scripts where autoconf.h lives, so C code can
+    // `#include <autoconf.h>` via DEP_KBUILD_CONFIG_INCLUDE
+    if let Some(kbuild_dir) = &kbuild_dir {
+        let autoconf_path = kbuild_dir.join("autoconf.h");
+        println!("cargo:include={}", kbuild_dir.display());
+        println!("cargo:autoconf={}", autoconf_path.display());
+        if autoconf_path.is_file() {
+            println!("cargo:rerun-if-changed={}", autoconf_path.display());
+        }
+    }
 }
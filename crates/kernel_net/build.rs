@@ -0,0 +1,4 @@
+// Apply .config cfgs when built with plain `cargo build`
+fn main() {
+    kbuild_build::configure();
+}
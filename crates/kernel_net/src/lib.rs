@@ -1,8 +1,9 @@
-use network_utils;
-
 pub fn net_init() {
     println!("🌐 [NET] Initializing network subsystem");
     
+    #[cfg(NET_MODULE)]
+    println!("🌐 [NET] Built as a module");
+    
     network_utils::init();
     println!("🌐 [NET] Network utilities loaded");
     
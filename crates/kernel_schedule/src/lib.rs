@@ -1,5 +1,25 @@
 use kernel_task::{self, Task};
 
+// Only the scheduler selected by DEFAULT_SCHEDULER is compiled in. CFS is
+// also used when no scheduler is selected (plain `cargo build`).
+#[cfg(any(
+    DEFAULT_SCHEDULER = "cfs",
+    not(any(DEFAULT_SCHEDULER = "rr", DEFAULT_SCHEDULER = "fifo"))
+))]
+mod policy {
+    pub const NAME: &str = "Completely Fair Scheduler";
+}
+
+#[cfg(DEFAULT_SCHEDULER = "rr")]
+mod policy {
+    pub const NAME: &str = "Round-Robin";
+}
+
+#[cfg(DEFAULT_SCHEDULER = "fifo")]
+mod policy {
+    pub const NAME: &str = "FIFO";
+}
+
 pub fn schedule_init() {
     println!("🔄 [SCHEDULE] Initializing scheduler");
     
@@ -17,6 +37,8 @@ pub fn schedule_init() {
     
     #[cfg(not(PREEMPT))]
     println!("🔄 [SCHEDULE] Cooperative scheduling");
+    
+    println!("🔄 [SCHEDULE] Policy: {}", policy::NAME);
 }
 
 pub fn schedule_on_cpu(task: &Task) {
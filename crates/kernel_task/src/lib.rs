@@ -1,6 +1,3 @@
-#[cfg(SMP)]
-use kernel_irq;
-
 pub struct Task {
     pub id: u32,
     #[cfg(SMP)]
@@ -2,10 +2,10 @@ use kernel_schedule::{schedule_init, schedule_on_cpu};
 use kernel_task::create_task;
 use legacy_driver::driver_init;
 
-#[cfg(NET)]
+#[cfg(feature = "NET")]
 use kernel_net::net_init;
 
-#[cfg(NET)]
+#[cfg(feature = "NET")]
 use demo_mixed_deps;
 
 fn main() {
@@ -36,14 +36,14 @@ fn main() {
     println!();
     
     // Initialize network subsystem (new)
-    #[cfg(NET)]
+    #[cfg(feature = "NET")]
     {
         net_init();
         println!();
     }
     
     // Demo mixed dependencies with config constants
-    #[cfg(NET)]
+    #[cfg(feature = "NET")]
     {
         demo_mixed_deps::demo();
         println!();
@@ -31,10 +31,19 @@ if [ -f ".cargo/config.toml" ]; then
     cat .cargo/config.toml
     echo ""
     
+    # check-cfg declarations go to kbuild-enabled crates only, never to
+    # the rustflags of every crate
+    if grep -q "KBUILD_OUT_DIR" .cargo/config.toml && ! grep -q "rustflags" .cargo/config.toml; then
+        echo "✅ Only KBUILD_OUT_DIR is set, no global rustflags"
+    else
+        echo "❌ Unexpected .cargo/config.toml content"
+        exit 1
+    fi
+    
     # Verify key declarations (should include configs from .config, not just features)
-    if grep -q "SMP" .cargo/config.toml && \
-       grep -q "NET" .cargo/config.toml && \
-       grep -q "ASYNC" .cargo/config.toml; then
+    if grep -q "SMP" target/kbuild/rustc-cfgs && \
+       grep -q "NET" target/kbuild/rustc-cfgs && \
+       grep -q "ASYNC" target/kbuild/rustc-cfgs; then
         echo "✅ All expected config declarations found"
     else
         echo "❌ Missing config declarations"
@@ -177,6 +186,169 @@ else
 fi
 echo
 
+echo "Test 11: 🧩 autoconf.h generation"
+echo "----------------------------------------------------"
+if [ -f "target/kbuild/autoconf.h" ] && \
+   grep -q "#define CONFIG_SMP 1" target/kbuild/autoconf.h && \
+   grep -q '#define CONFIG_DEFAULT_SCHEDULER "cfs"' target/kbuild/autoconf.h; then
+    echo "✅ autoconf.h generated with expected defines"
+else
+    echo "❌ autoconf.h missing or incomplete"
+    exit 1
+fi
+
+# A dependent of kbuild_config compiles C code against autoconf.h through
+# the DEP_KBUILD_CONFIG_INCLUDE path exported by kbuild_config's build script
+AUTOCONF_USER=$(mktemp -d)
+mkdir -p "$AUTOCONF_USER/src"
+cat > "$AUTOCONF_USER/Cargo.toml" << EOF
+[package]
+name = "autoconf_user"
+version = "0.1.0"
+edition = "2021"
+
+[dependencies]
+kbuild_config = { path = "$PWD/crates/kbuild_config" }
+
+[workspace]
+EOF
+cat > "$AUTOCONF_USER/build.rs" << 'EOF'
+use std::env;
+use std::process::Command;
+
+fn main() {
+    let include = env::var("DEP_KBUILD_CONFIG_INCLUDE").expect("DEP_KBUILD_CONFIG_INCLUDE not set");
+    let status = Command::new(env::var("CC").unwrap_or_else(|_| "cc".to_string()))
+        .args(["-fsyntax-only", "-I", &include, "smp.c"])
+        .status()
+        .expect("failed to run the C compiler");
+    assert!(status.success(), "smp.c does not compile against {}/autoconf.h", include);
+}
+EOF
+cat > "$AUTOCONF_USER/smp.c" << 'EOF'
+#include <autoconf.h>
+#ifndef CONFIG_SMP
+#error "CONFIG_SMP is not set in autoconf.h"
+#endif
+EOF
+echo "" > "$AUTOCONF_USER/src/lib.rs"
+if (cd "$AUTOCONF_USER" && cargo build --target-dir "$PWD/target" > /dev/null 2>&1); then
+    echo "✅ A dependent's build script compiled C code against autoconf.h"
+else
+    echo "❌ C code could not include autoconf.h via DEP_KBUILD_CONFIG_INCLUDE"
+    (cd "$AUTOCONF_USER" && cargo build --target-dir "$PWD/target" 2>&1 | tail -10)
+    rm -rf "$AUTOCONF_USER"
+    exit 1
+fi
+rm -rf "$AUTOCONF_USER"
+echo
+
+echo "Test 12: ♻️  Back-to-back builds are a no-op"
+echo "----------------------------------------------------"
+./target/debug/cargo-kbuild build --kconfig .config > /dev/null 2>&1
+if ./target/debug/cargo-kbuild build --kconfig .config 2>&1 | grep -qE "Compiling|📝 Generated"; then
+    echo "❌ Second build regenerated files or recompiled crates"
+    exit 1
+else
+    echo "✅ Unchanged configuration leaves generated files and build untouched"
+fi
+echo
+
+echo "Test 13: 🔎 check-cfg covers symbols missing from the config"
+echo "----------------------------------------------------"
+grep -v '^DEBUG=' .config > /tmp/kbuild_no_debug.config
+if ./target/debug/cargo-kbuild build --kconfig /tmp/kbuild_no_debug.config 2>&1 | grep -q "unexpected \`cfg\`"; then
+    echo "❌ Removing DEBUG from the config caused unexpected cfg warnings"
+    rm -f /tmp/kbuild_no_debug.config
+    exit 1
+else
+    echo "✅ Symbols declared in Kconfig stay known to check-cfg"
+fi
+rm -f /tmp/kbuild_no_debug.config
+./target/debug/cargo-kbuild build --kconfig .config > /dev/null 2>&1
+echo
+
+echo "Test 14: 🧵 String-form build.rustflags in rustflags cfg-mode"
+echo "----------------------------------------------------"
+cp Cargo.toml Cargo.toml.backup
+sed -i 's/^\[workspace.metadata.kbuild\]$/[workspace.metadata.kbuild]\ncfg-mode = "rustflags"/' Cargo.toml
+mv .cargo/config.toml .cargo/config.toml.backup
+printf '[build]\nrustflags = "-C debuginfo=1"\n' > .cargo/config.toml
+# A string value with a space cannot be appended to a string-form entry
+cp .config /tmp/kbuild_board.config
+echo 'BOARD_NAME="my board"' >> /tmp/kbuild_board.config
+OUTPUT=$(./target/debug/cargo-kbuild build --kconfig .config 2>&1 || true)
+BOARD_OUTPUT=$(./target/debug/cargo-kbuild build --kconfig /tmp/kbuild_board.config 2>&1 || true)
+rm -f /tmp/kbuild_board.config
+if echo "$OUTPUT" | grep -q "Command completed successfully" && ! echo "$OUTPUT" | grep -q "failed to merge" && \
+   echo "$BOARD_OUTPUT" | grep -q "Command completed successfully"; then
+    echo "✅ kbuild flags merged with string-form build.rustflags"
+else
+    echo "❌ Build failed with string-form build.rustflags"
+    echo "$OUTPUT" | tail -5
+    echo "$BOARD_OUTPUT" | tail -5
+    mv .cargo/config.toml.backup .cargo/config.toml
+    mv Cargo.toml.backup Cargo.toml
+    exit 1
+fi
+mv .cargo/config.toml.backup .cargo/config.toml
+mv Cargo.toml.backup Cargo.toml
+./target/debug/cargo-kbuild build --kconfig .config > /dev/null 2>&1
+echo
+
+echo "Test 15: 🧮 Toggling a symbol rebuilds only crates that use it"
+echo "----------------------------------------------------"
+sed 's/^PREEMPT=y/PREEMPT=n/' .config > /tmp/kbuild_no_preempt.config
+OUTPUT=$(./target/debug/cargo-kbuild build --kconfig /tmp/kbuild_no_preempt.config 2>&1)
+if echo "$OUTPUT" | grep -q "Compiling kernel_schedule" && ! echo "$OUTPUT" | grep -q "Compiling kernel_irq"; then
+    echo "✅ PREEMPT change rebuilt kernel_schedule but not kernel_irq"
+else
+    echo "❌ Unexpected rebuild set after changing PREEMPT"
+    rm -f /tmp/kbuild_no_preempt.config
+    exit 1
+fi
+rm -f /tmp/kbuild_no_preempt.config
+./target/debug/cargo-kbuild build --kconfig .config > /dev/null 2>&1
+echo
+
+echo "Test 16: 📂 Running from a member crate directory"
+echo "----------------------------------------------------"
+if (cd crates/kernel_net && ../../target/debug/cargo-kbuild check 2>&1 | grep -q "Command completed successfully"); then
+    echo "✅ Workspace root and .config found from crates/kernel_net"
+else
+    echo "❌ cargo-kbuild failed when run from crates/kernel_net"
+    exit 1
+fi
+echo
+
+echo "Test 17: 🪶 Weak sub-feature of a renamed kbuild dependency (should fail)"
+echo "----------------------------------------------------"
+cp crates/kernel_net/Cargo.toml crates/kernel_net/Cargo.toml.backup
+cat > crates/kernel_net/Cargo.toml << 'EOF'
+[package]
+name = "kernel_net"
+version = "0.1.0"
+edition = "2021"
+
+[package.metadata.kbuild]
+enabled = true
+
+[dependencies]
+utils = { package = "network_utils", path = "../network_utils", optional = true }
+
+[features]
+NET = ["dep:utils", "utils?/ASYNC"]
+EOF
+if ./target/debug/cargo-kbuild build --kconfig .config 2>&1 | grep -q "Dependency 'utils' (package 'network_utils') is kbuild-enabled"; then
+    echo "✅ Validation rejected utils?/ASYNC"
+else
+    echo "❌ Validation should have rejected utils?/ASYNC"
+    mv crates/kernel_net/Cargo.toml.backup crates/kernel_net/Cargo.toml
+    exit 1
+fi
+mv crates/kernel_net/Cargo.toml.backup crates/kernel_net/Cargo.toml
+echo
+
 echo "=============================================="
 echo "🎉 All tests completed"
 